@@ -0,0 +1,101 @@
+//! Append-style array keys, written as `key[] = value` once for each element.
+
+use super::*;
+
+impl IniDocument {
+    /// Get every value of an array key in a given section, in the order they were added.
+    /// 
+    /// Array keys are kept separately from ordinary keys, so `items` and `items[]` can both exist in the same section,
+    /// and [`get`](IniDocument::get) never returns an array's values.
+    /// They're parsed from `key[] = value` lines when [`IniParser::array_keys`] is on, and written back out the same way.
+    /// 
+    /// ```
+    /// # use innit::*;
+    /// let document = IniParser::new().array_keys(true).parse("items[] = a\nitems[] = b").unwrap();
+    /// assert_eq!(document.get_all("items", ""), Some(&["a".to_string(), "b".to_string()][..]));
+    /// assert_eq!(document.get("items", ""), None);
+    /// ```
+    pub fn get_all<T: AsRef<str>>(&self, key: T, section: T) -> Option<&[String]> {
+        self.arrays.get(self.lookup_section(section.as_ref()))?.get(key.as_ref()).map(|a| a.as_slice())
+    }
+    /// Add a value to the end of an array key in a given section, creating the key and the section if they don't exist.
+    pub fn push<T, U, V>(&mut self, key: T, value: U, section: V)
+    where T: Into<String>, U: Into<String>, V: Into<String> {
+        let section = self.stored_section(section.into());
+        if !self.sections.contains_key(&section) {
+            // the section has to exist to be written out
+            self.sections.insert(section.clone(), Section::default());
+            self.index_section_changed(&section)
+        }
+        let key = self.stored_name(key.into());
+        self.arrays.entry(section).or_default().entry(key).or_default().push(value.into())
+    }
+    /// Remove an array key and all of its values from a given section. Returns the values, if the key existed.
+    pub fn remove_all<T: AsRef<str>>(&mut self, key: T, section: T) -> Option<Vec<String>> {
+        self.arrays.get_mut(section.as_ref())?.remove(key.as_ref())
+    }
+
+    /// the array keys in a section
+    pub(crate) fn section_arrays(&self, section: &str) -> Option<&Map<String, Vec<String>>> {
+        self.arrays.get(section).filter(|a| !a.is_empty())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse() {
+        let ini = r"[list]
+items[] = one
+items[] = two
+items [] = three
+items = plain";
+        let document = IniParser::new().array_keys(true).parse(ini).unwrap();
+        let items: Vec<String> = vec!["one".into(), "two".into(), "three".into()];
+        assert_eq!(document.get_all("items", "list"), Some(items.as_slice()));
+        assert_eq!(document.get("items", "list"), Some("plain"));
+
+        let document = IniParser::new().parse(ini).unwrap();
+        assert_eq!(document.get_all("items", "list"), None);
+        assert_eq!(document.get("items[]", "list"), Some("two"));
+    }
+
+    #[test]
+    fn round_trip() {
+        let ini = r"items[] = one
+items[] = two
+items[] = three";
+        let parser = IniParser::new().array_keys(true);
+        let document = parser.parse(ini).unwrap();
+        let written = document.to_string_sorted();
+        assert_eq!(written, ["items[] = one", "items[] = two", "items[] = three", ""].join(LINE_DELIM));
+        assert_eq!(parser.parse(written).unwrap(), document);
+    }
+
+    #[test]
+    fn push_and_remove() {
+        let mut document = IniDocument::empty();
+        document.push("items", "one", "list");
+        document.push("items", "two", "list");
+        document.insert("foo", "bar", "list");
+        assert!(!document.is_empty());
+        assert_eq!(document.to_string(), ["[list]", "foo = bar", "items[] = one", "items[] = two", ""].join(LINE_DELIM));
+
+        assert_eq!(document.len(), 3);
+
+        // the two always agree, even with only array values
+        let document = IniParser::new().array_keys(true).parse("a[] = 1").unwrap();
+        assert!(!document.is_empty());
+        assert_eq!(document.len(), 1);
+        let mut document = IniDocument::empty();
+        document.push("items", "one", "list");
+        document.push("items", "two", "list");
+        document.rename_section("list", "other").unwrap();
+        assert_eq!(document.get_all("items", "other").map(|a| a.len()), Some(2));
+
+        assert_eq!(document.remove_all("items", "other"), Some(vec!["one".into(), "two".into()]));
+        assert_eq!(document.get_all("items", "other"), None);
+    }
+}
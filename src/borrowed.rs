@@ -0,0 +1,168 @@
+//! Read-only documents that borrow their keys and values from the string they were parsed from.
+
+use super::*;
+use std::borrow::Cow;
+
+/// A read-only document that borrows its keys and values from the string it was parsed from, as returned by [`IniDocument::parse_borrowed`].
+/// 
+/// This avoids allocating a new string for every key and value, which is useful for reading large documents that live for a long time.
+/// Use [`to_owned`](IniDocumentRef::to_owned) to turn it into an [`IniDocument`] that can be changed.
+/// 
+/// ```
+/// # use innit::*;
+/// let ini = String::from("foo = bar\n[section1]\nfoo = baz");
+/// let document = IniDocument::parse_borrowed(&ini).unwrap();
+/// assert_eq!(document.get("foo", ""), Some("bar"));
+/// assert_eq!(document.get_section("section1").unwrap().get("foo"), Some("baz"));
+/// assert_eq!(document.to_owned(), IniDocument::from_string(&ini).unwrap());
+/// ```
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct IniDocumentRef<'a> {
+    /// names are only owned if they had escapes in them
+    sections: Map<Cow<'a, str>, SectionRef<'a>>,
+}
+/// The keys and values of a single section of an [`IniDocumentRef`], borrowed from the string it was parsed from.
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct SectionRef<'a>(Map<&'a str, &'a str>);
+
+impl IniDocument {
+    /// Parse a document from a string, borrowing the keys and values from it instead of copying them.
+    /// 
+    /// This follows the same rules as [`from_string`](IniDocument::from_string) and fails in the same ways,
+    /// but comments aren't kept, since the document can't be written back out.
+    pub fn parse_borrowed(s: &str) -> Result<IniDocumentRef<'_>, InnitError> {
+        let parser = IniParser::new();
+        let body = s.strip_prefix('\u{FEFF}').unwrap_or(s);
+        let mut document = IniDocumentRef::default();
+        let mut section = Cow::Borrowed("");
+        let mut start = s.len() - body.len();
+        for (lnum, raw) in body.split('\n').enumerate() {
+            let span = start..start + raw.strip_suffix('\r').unwrap_or(raw).len();
+            start += raw.len() + 1;
+            let line = raw.trim();
+            if line.is_empty() || parser.string_is_comment(line).is_some() {
+                continue
+            }
+            if let Some(name) = string_is_section_start(line, true) {
+                if name == "" {
+                    return Err(InnitError::EmptyStringSection(line.into(), lnum + 1, span))
+                }
+                section = name;
+                continue
+            }
+            match parse_k_v(line, false, &['=']).map(|(k, _, v)| (k.trim(), v.trim())) {
+                Some((k, v)) => {
+                    // headers on their own don't make a section, the same as in owned documents
+                    document.sections.entry(section.clone()).or_default().0.insert(k, v);
+                }
+                None if line.starts_with('[') || line.ends_with(']') => {
+                    return Err(InnitError::MalformedSection { text: line.into(), line: lnum + 1, span })
+                }
+                None => return Err(InnitError::MissingEquals(line.into(), lnum + 1, span))
+            }
+        }
+        Ok(document)
+    }
+}
+
+impl<'a> IniDocumentRef<'a> {
+    /// Get the value of a key in a given section. The value borrows from the parsed string, so it can outlive the document.
+    pub fn get<T: AsRef<str>>(&self, key: T, section: T) -> Option<&'a str> {
+        self.sections.get(section.as_ref())?.get(key)
+    }
+    /// Get an entire document section.
+    pub fn get_section<T: AsRef<str>>(&self, section: T) -> Option<&SectionRef<'a>> {
+        self.sections.get(section.as_ref())
+    }
+    /// Get the number of sections in the document, including the unnamed section if it has any keys.
+    pub fn section_count(&self) -> usize {
+        self.sections.len()
+    }
+    /// Copy the document into an [`IniDocument`], so it can be changed and written out.
+    pub fn to_owned(&self) -> IniDocument {
+        let mut document = IniDocument::empty();
+        for (name, section) in &self.sections {
+            document.extend_section(name.as_ref(), section.iter())
+        }
+        document
+    }
+}
+
+impl<'a> SectionRef<'a> {
+    /// Get the value of a key.
+    pub fn get<T: AsRef<str>>(&self, key: T) -> Option<&'a str> {
+        self.0.get(key.as_ref()).copied()
+    }
+    /// Determine if the section contains a key.
+    pub fn contains_key<T: AsRef<str>>(&self, key: T) -> bool {
+        self.0.contains_key(key.as_ref())
+    }
+    /// Get the number of key/value pairs in the section.
+    pub fn len(&self) -> usize {
+        self.0.len()
+    }
+    /// Determine if the section has no keys.
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+    /// Iterate over the keys and values in the section, in the order they're stored in.
+    pub fn iter(&self) -> impl Iterator<Item = (&'a str, &'a str)> + '_ {
+        self.0.iter().map(|(k, v)| (*k, *v))
+    }
+    /// Iterate over the keys in the section, in the order they're stored in.
+    pub fn keys(&self) -> impl Iterator<Item = &'a str> + '_ {
+        self.0.keys().copied()
+    }
+    /// Iterate over the values in the section, in the order they're stored in.
+    pub fn values(&self) -> impl Iterator<Item = &'a str> + '_ {
+        self.0.values().copied()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn matches_owned() {
+        let ini = "\u{FEFF}foo = bar\r\n# comment\r\n[section1]\r\nfoo = baz\r\nfoo = bop\r\n[a\\]b]\r\nx = y\r\n[empty]\r\n";
+        let borrowed = IniDocument::parse_borrowed(ini).unwrap();
+        let owned = IniDocument::from_string(ini).unwrap();
+        assert_eq!(borrowed.get("foo", "section1"), Some("bop"));
+        assert_eq!(borrowed.get("x", "a]b"), Some("y"));
+        assert_eq!(borrowed.get("foo", "section2"), None);
+        assert!(borrowed.get_section("empty").is_none());
+        assert_eq!(borrowed.section_count(), owned.section_count());
+        assert_eq!(borrowed.to_owned(), owned);
+
+        for ini in ["foo = bar\nbeans", "[]", "[abc", "\u{FEFF}a = b\r\nabc]"] {
+            assert_eq!(IniDocument::parse_borrowed(ini), Err(IniDocument::from_string(ini).unwrap_err()));
+        }
+    }
+
+    #[test]
+    fn large_document_borrows_everything() {
+        let mut ini = String::new();
+        for section in 0..100 {
+            ini.push_str(&format!("[section{}]\n", section));
+            for key in 0..100 {
+                ini.push_str(&format!("key{} = value {} {}\n", key, section, key));
+            }
+        }
+        let document = IniDocument::parse_borrowed(&ini).unwrap();
+        assert_eq!(document.section_count(), 100);
+
+        // every key and value points into the input, so none of them were copied
+        let input = ini.as_bytes().as_ptr_range();
+        let borrowed = |s: &str| input.contains(&s.as_ptr()) && s.len() <= input.end as usize - s.as_ptr() as usize;
+        let mut pairs = 0;
+        for section in 0..100 {
+            for (k, v) in document.get_section(format!("section{}", section)).unwrap().iter() {
+                assert!(borrowed(k) && borrowed(v));
+                pairs += 1
+            }
+        }
+        assert_eq!(pairs, 100 * 100);
+        assert_eq!(document.get("key42", "section7"), Some("value 7 42"));
+    }
+}
@@ -0,0 +1,569 @@
+//! Case-insensitive access, behind the `case_insensitive` feature.
+
+use super::*;
+use std::borrow::Cow;
+
+/// How names are compared by the case-insensitive methods.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum CaseFolding {
+    /// Only fold ASCII letters, so `FOO` matches `foo` but `É` doesn't match `é`.
+    /// This never allocates, and is the default, since INI names are almost always ASCII.
+    #[default]
+    Ascii,
+    /// Fold using full Unicode lowercasing, so `É` matches `é`.
+    /// This allocates on every comparison, and some mappings can be surprising,
+    /// eg. `İ` lowercases to `i̇` (an `i` followed by a combining dot), which doesn't match a plain `i`.
+    Unicode,
+}
+impl CaseFolding {
+    /// Compare two strings, ignoring case according to this folding strategy.
+    pub fn fold_eq(&self, a: &str, b: &str) -> bool {
+        match self {
+            CaseFolding::Ascii => a.eq_ignore_ascii_case(b),
+            CaseFolding::Unicode => a == b || a.to_lowercase() == b.to_lowercase()
+        }
+    }
+    /// Fold a string to a form where two strings are equal if and only if [`fold_eq`](CaseFolding::fold_eq) says they are.
+    pub fn fold(&self, s: &str) -> String {
+        match self {
+            CaseFolding::Ascii => s.to_ascii_lowercase(),
+            CaseFolding::Unicode => s.to_lowercase()
+        }
+    }
+}
+
+/// Which names the case-insensitive methods ignore case in.
+/// 
+/// By default, both section names and keys are case-insensitive.
+/// Like Windows INI files, section names can be case-insensitive while keys are matched exactly, or the other way round:
+/// 
+/// ```
+/// # use innit::*;
+/// let mut document = IniDocument::from_string("[Section]\nKey = value").unwrap();
+/// document.set_case_insensitivity(CaseInsensitivity { sections: true, keys: false });
+/// assert_eq!(document.get_case_insensitive("Key", "section"), Some("value"));
+/// assert_eq!(document.get_case_insensitive("key", "section"), None);
+/// ```
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct CaseInsensitivity {
+    /// Ignore case in section names.
+    pub sections: bool,
+    /// Ignore case in keys.
+    pub keys: bool,
+}
+impl Default for CaseInsensitivity {
+    fn default() -> CaseInsensitivity {
+        CaseInsensitivity { sections: true, keys: true }
+    }
+}
+
+/// What case section names and keys are stored in, as set with [`IniDocument::set_name_case`] or [`IniParser::name_case`].
+/// 
+/// This is separate from how names are matched by the case-insensitive methods, so names can be tidied up when they're stored,
+/// while lookups stay tolerant of any case.
+/// 
+/// ```
+/// # use innit::*;
+/// let document = IniParser::new().name_case(NameCase::Lower).parse("[Section]\nKEY = Value").unwrap();
+/// assert_eq!(document.get("key", "section"), Some("Value"));
+/// assert_eq!(document.get_case_insensitive("Key", "SECTION"), Some("Value"));
+/// ```
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum NameCase {
+    /// Keep names as they are.
+    #[default]
+    Preserve,
+    /// Store names in lowercase.
+    Lower,
+    /// Store names in uppercase.
+    Upper,
+}
+impl NameCase {
+    /// Put a name into this case.
+    pub fn apply<'a>(&self, name: Cow<'a, str>) -> Cow<'a, str> {
+        match self {
+            NameCase::Preserve => name,
+            NameCase::Lower if !name.chars().any(char::is_uppercase) => name,
+            NameCase::Upper if !name.chars().any(char::is_lowercase) => name,
+            NameCase::Lower => Cow::Owned(name.to_lowercase()),
+            NameCase::Upper => Cow::Owned(name.to_uppercase()),
+        }
+    }
+}
+
+/// Folded names mapped to the actual names they came from.
+/// More than one actual name can fold to the same thing, but there's usually only one.
+type FoldMap = HashMap<String, Vec<String>>;
+
+/// An index from folded section and key names to the actual names in the document.
+#[derive(Debug, Default, Clone, PartialEq)]
+pub(crate) struct CaseIndex {
+    sections: FoldMap,
+    /// indexed by actual section name
+    keys: HashMap<String, FoldMap>,
+}
+impl CaseIndex {
+    fn add_section(&mut self, folding: CaseFolding, section: &str) {
+        let names = self.sections.entry(folding.fold(section)).or_default();
+        if !names.iter().any(|n| n == section) {
+            names.push(section.into())
+        }
+    }
+    fn remove_section(&mut self, folding: CaseFolding, section: &str) {
+        remove_folded(&mut self.sections, folding.fold(section), section);
+        self.keys.remove(section);
+    }
+    fn add_key(&mut self, folding: CaseFolding, key: &str, section: &str) {
+        self.add_section(folding, section);
+        let names = self.keys.entry(section.into()).or_default().entry(folding.fold(key)).or_default();
+        if !names.iter().any(|n| n == key) {
+            names.push(key.into())
+        }
+    }
+    fn remove_key(&mut self, folding: CaseFolding, key: &str, section: &str) {
+        if let Some(keys) = self.keys.get_mut(section) {
+            remove_folded(keys, folding.fold(key), key)
+        }
+    }
+}
+fn remove_folded(map: &mut FoldMap, folded: String, actual: &str) {
+    if let Some(names) = map.get_mut(&folded) {
+        names.retain(|n| n != actual);
+        if names.is_empty() {
+            map.remove(&folded);
+        }
+    }
+}
+
+impl IniDocument {
+    /// Get the folding strategy used by the case-insensitive methods.
+    pub fn case_folding(&self) -> CaseFolding {
+        self.case_folding
+    }
+    /// Set the folding strategy used by the case-insensitive methods. Defaults to [`CaseFolding::Ascii`].
+    pub fn set_case_folding(&mut self, folding: CaseFolding) {
+        self.case_folding = folding;
+        if self.case_index.is_some() {
+            self.set_case_index(true)
+        }
+    }
+    /// Get which names the case-insensitive methods ignore case in.
+    pub fn case_insensitivity(&self) -> CaseInsensitivity {
+        self.case_insensitivity
+    }
+    /// Set which names the case-insensitive methods ignore case in. Defaults to both section names and keys.
+    pub fn set_case_insensitivity(&mut self, insensitivity: CaseInsensitivity) {
+        self.case_insensitivity = insensitivity
+    }
+    /// Get the case that section names and keys are stored in.
+    pub fn name_case(&self) -> NameCase {
+        self.name_case
+    }
+    /// Set the case that section names and keys are stored in when they're inserted from now on. Defaults to [`NameCase::Preserve`].
+    /// Names that are already in the document are left as they are.
+    pub fn set_name_case(&mut self, case: NameCase) {
+        self.name_case = case
+    }
+    /// the name a section or key is stored under
+    pub(crate) fn stored_name(&self, name: String) -> String {
+        self.name_case.apply(Cow::Owned(name)).into_owned()
+    }
+    /// Determine if the document keeps an index for case-insensitive lookups.
+    pub fn has_case_index(&self) -> bool {
+        self.case_index.is_some()
+    }
+    /// Turn the case-insensitive index on or off. Off by default.
+    /// 
+    /// Without the index, every case-insensitive lookup compares against every section name and every key in the matching section.
+    /// With it, lookups are hash lookups, at the cost of some memory and a little extra work on every insert and removal.
+    /// The original names are still used when writing the document out.
+    pub fn set_case_index(&mut self, enabled: bool) {
+        self.case_index = None;
+        if enabled {
+            let mut index = CaseIndex::default();
+            for (name, data) in &self.sections {
+                index.add_section(self.case_folding, name);
+                for k in data.keys() {
+                    index.add_key(self.case_folding, k, name)
+                }
+            }
+            self.case_index = Some(index)
+        }
+    }
+
+    pub(crate) fn index_key_inserted(&mut self, key: &str, section: &str) {
+        if let Some(index) = &mut self.case_index {
+            index.add_key(self.case_folding, key, section)
+        }
+    }
+    pub(crate) fn index_key_removed(&mut self, key: &str, section: &str) {
+        if let Some(index) = &mut self.case_index {
+            index.remove_key(self.case_folding, key, section)
+        }
+    }
+    pub(crate) fn index_section_changed(&mut self, section: &str) {
+        if let Some(index) = &mut self.case_index {
+            index.remove_section(self.case_folding, section);
+            if let Some(data) = self.sections.get(section) {
+                index.add_section(self.case_folding, section);
+                for k in data.keys() {
+                    index.add_key(self.case_folding, k, section)
+                }
+            }
+        }
+    }
+
+    /// find the actual name of a section, preferring an exact match
+    fn actual_section(&self, section: &str) -> Option<&str> {
+        let exact = self.sections.get_key_value(section).map(|(name, _)| name.as_str());
+        if exact.is_some() || !self.case_insensitivity.sections {
+            return exact
+        }
+        match &self.case_index {
+            Some(index) => index.sections.get(&self.case_folding.fold(section))?.first().map(|s| s.as_str()),
+            None => self.sections.keys().find(|name| self.case_folding.fold_eq(name, section)).map(|s| s.as_str())
+        }
+    }
+    /// find the actual name of a key, given the actual name of its section, preferring an exact match
+    fn actual_key(&self, key: &str, section: &str) -> Option<&str> {
+        let exact = self.sections.get(section)?.0.get_key_value(key).map(|(k, _)| k.as_str());
+        if exact.is_some() || !self.case_insensitivity.keys {
+            return exact
+        }
+        match &self.case_index {
+            Some(index) => index.keys.get(section)?.get(&self.case_folding.fold(key))?.first().map(|s| s.as_str()),
+            None => self.sections.get(section)?.keys().find(|k| self.case_folding.fold_eq(k, key))
+        }
+    }
+
+    /// Get a reference to a value in a given section, using case-insensitive matching.
+    pub fn get_case_insensitive<T: AsRef<str>>(&self, key: T, section: T) -> Option<&str> {
+        let section = self.actual_section(section.as_ref())?;
+        let key = self.actual_key(key.as_ref(), section)?;
+        self.get(key, section)
+    }
+    /// Get a section, using case-insensitive matching.
+    pub fn get_section_case_insensitive<T: AsRef<str>>(&self, section: T) -> Option<&Section> {
+        self.get_section(self.actual_section(section.as_ref())?)
+    }
+
+    /// Determine if a key exists in a given section and has exactly the expected value, using case-insensitive matching for the names.
+    /// The value itself is still compared exactly.
+    pub fn value_is_case_insensitive<T: AsRef<str>>(&self, key: T, section: T, expected: T) -> bool {
+        self.get_case_insensitive(key, section) == Some(expected.as_ref())
+    }
+
+    /// Iterate over the sections whose names start with a prefix, like [`sections_with_prefix`](IniDocument::sections_with_prefix),
+    /// using case-insensitive matching.
+    pub fn sections_with_prefix_case_insensitive<'a>(&'a self, prefix: &'a str) -> impl Iterator<Item = (&'a str, &'a Section)> {
+        let folded = self.case_folding.fold(prefix);
+        self.sections.iter()
+            .filter(move |(name, _)| {
+                if self.case_insensitivity.sections {
+                    self.case_folding.fold(name).starts_with(&folded)
+                }
+                else {
+                    name.starts_with(prefix)
+                }
+            })
+            .map(|(name, s)| (name.as_str(), s))
+    }
+
+    /// Remove a key/value pair in a given section, using case-insensitive matching. Returns the value, if it existed.
+    pub fn remove_case_insensitive<T: AsRef<str>>(&mut self, key: T, section: T) -> Option<String> {
+        let section = self.actual_section(section.as_ref())?.to_string(); // store these to appease the borrow checker
+        let key = self.actual_key(key.as_ref(), &section)?.to_string();
+        self.remove(&key, &section)
+    }
+    /// Remove a section, using case-insensitive matching. Returns the section, if it existed.
+    pub fn remove_section_case_insensitive<T: AsRef<str>>(&mut self, section: T) -> Option<Section> {
+        let section = self.actual_section(section.as_ref())?.to_string();
+        self.remove_section(section)
+    }
+
+    /// Compare two documents, ignoring case in names the way this document's case-insensitive methods do.
+    /// Values are still compared exactly, and comments aren't compared.
+    /// 
+    /// ```
+    /// # use innit::*;
+    /// let a = IniDocument::from_string("[Section]\nKey = value").unwrap();
+    /// let b = IniDocument::from_string("[SECTION]\nkey = value").unwrap();
+    /// assert_ne!(a, b);
+    /// assert!(a.eq_case_insensitive(&b));
+    /// ```
+    pub fn eq_case_insensitive(&self, other: &IniDocument) -> bool {
+        self.folded_entries(self) == self.folded_entries(other)
+    }
+    /// every key in a document with its value, with names folded according to this document's settings, in sorted order
+    fn folded_entries<'a>(&self, document: &'a IniDocument) -> Vec<(Cow<'a, str>, Cow<'a, str>, Vec<&'a str>)> {
+        let fold = |name: &'a str, insensitive: bool| if insensitive { Cow::Owned(self.case_folding.fold(name)) } else { Cow::Borrowed(name) };
+        let mut entries = Vec::new();
+        for (name, section) in &document.sections {
+            let s = fold(name, self.case_insensitivity.sections);
+            for (k, v) in section.iter() {
+                entries.push((s.clone(), fold(k, self.case_insensitivity.keys), vec![v]))
+            }
+            for (k, values) in document.section_arrays(name).into_iter().flatten() {
+                // array keys can't clash with ordinary keys, so they're kept apart with the brackets they're written with
+                let k = format!("{}[]", fold(k, self.case_insensitivity.keys));
+                entries.push((s.clone(), Cow::Owned(k), values.iter().map(|v| v.as_str()).collect()))
+            }
+        }
+        entries.sort();
+        entries
+    }
+}
+
+impl IniParser {
+    /// Match section names and keys case-insensitively while parsing, so that eg. `[Section]` and `[section]` are the same section,
+    /// and the spelling that appears first is kept. Off by default, which keeps names that only differ in case apart.
+    /// 
+    /// The parsed document is given the same [`CaseInsensitivity`], so the case-insensitive methods match names the same way.
+    pub fn case_insensitivity(mut self, insensitivity: CaseInsensitivity) -> IniParser {
+        self.case_insensitivity = Some(insensitivity);
+        self
+    }
+
+    /// Store section names and keys in the given case, so eg. `[Section]` and `KEY` are stored as `section` and `key` with [`NameCase::Lower`],
+    /// and written back out that way. This is separate from [`case_insensitivity`](IniParser::case_insensitivity), which only affects matching.
+    /// Defaults to [`NameCase::Preserve`].
+    /// 
+    /// The parsed document is given the same [`NameCase`], so names inserted later are stored in the same case.
+    /// Lines kept as they were by [`preserve_formatting`](IniParser::preserve_formatting) aren't changed.
+    pub fn name_case(mut self, case: NameCase) -> IniParser {
+        self.name_case = case;
+        self
+    }
+
+    /// get a document ready to be parsed into
+    pub(crate) fn prepare(&self, document: &mut IniDocument) {
+        if let Some(insensitivity) = self.case_insensitivity {
+            document.set_case_insensitivity(insensitivity)
+        }
+        if self.name_case != NameCase::Preserve {
+            document.set_name_case(self.name_case)
+        }
+    }
+    /// the name a section should be stored under, which might be an existing one that only differs in case
+    pub(crate) fn section_name(&self, document: &IniDocument, name: Cow<'_, str>) -> String {
+        let name = self.name_case.apply(name);
+        match self.case_insensitivity.and_then(|_| document.actual_section(&name)) {
+            Some(actual) => actual.into(),
+            None => name.into_owned()
+        }
+    }
+    /// the name a key should be stored under, which might be an existing one that only differs in case
+    pub(crate) fn key_name<'a>(&self, document: &IniDocument, key: Cow<'a, str>, section: &str) -> Cow<'a, str> {
+        let key = self.name_case.apply(key);
+        match self.case_insensitivity.and_then(|_| document.actual_key(&key, section)) {
+            Some(actual) => Cow::Owned(actual.into()),
+            None => key
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn ci() {
+        let ini = r"foo = bar
+# comment
+; comment
+BAZ=bop
+[section1]
+foo = baz";
+        let document = IniDocument::from_string(ini);
+        assert!(document.is_ok());
+        let document = document.unwrap();
+
+        assert_eq!(document.get_case_insensitive("FOO", ""), Some("bar"));
+        assert_eq!(document.get_case_insensitive("baz", ""), Some("bop"));
+        assert_eq!(document.get_case_insensitive("foo", "SECtion1"), Some("baz"));
+
+        assert!(document.value_is_case_insensitive("FOO", "SECTION1", "baz"));
+        assert!(!document.value_is_case_insensitive("FOO", "SECTION1", "BAZ"));
+        assert!(!document.value_is_case_insensitive("qux", "section1", "baz"));
+    }
+
+    #[test]
+    fn prefix() {
+        let mut document = IniDocument::from_string("[Service:Web]\nport = 80\n[service:db]\nport = 5432\n[logging]\nlevel = info").unwrap();
+        let mut found: Vec<&str> = document.sections_with_prefix_case_insensitive("SERVICE:").map(|(name, _)| name).collect();
+        found.sort();
+        assert_eq!(found, ["Service:Web", "service:db"]);
+        document.set_case_insensitivity(CaseInsensitivity { sections: false, keys: true });
+        assert_eq!(document.sections_with_prefix_case_insensitive("service:").count(), 1);
+    }
+
+    #[test]
+    fn name_case() {
+        let ini = "Top = 1\n[Section]\nKEY = Value\n[SECTION]\nOther = Thing";
+        let parser = IniParser::new().name_case(NameCase::Lower);
+        let mut document = parser.parse(ini).unwrap();
+        assert_eq!(document.name_case(), NameCase::Lower);
+        assert_eq!(document.get("key", "section"), Some("Value"));
+        assert_eq!(document.get_case_insensitive("KEY", "Section"), Some("Value"));
+        let options = FormatOptions { sorted: true, line_ending: LineEnding::Lf, ..Default::default() };
+        assert_eq!(document.to_string_with(&options), "top = 1\n[section]\nkey = Value\nother = Thing\n");
+
+        // later inserts are normalized too
+        document.insert("NewKey", "x", "Section");
+        document.push("Items", "y", "NEW");
+        document.append("PATH", "", "/bin", ":");
+        document.append("PATH", "", "/sbin", ":");
+        assert_eq!(document.get("path", ""), Some("/bin:/sbin"));
+        assert_eq!(document.get("newkey", "section"), Some("x"));
+        assert_eq!(document.get_all("items", "new"), Some(&["y".to_string()][..]));
+
+        let document = IniParser::new().name_case(NameCase::Upper).parse(ini).unwrap();
+        assert_eq!(document.get("KEY", "SECTION"), Some("Value"));
+        assert_eq!(document.get("TOP", ""), Some("1"));
+
+        let mut document = IniParser::new().parse(ini).unwrap();
+        assert_eq!(document.get("KEY", "Section"), Some("Value"));
+        document.set_name_case(NameCase::Lower);
+        document.insert("Added", "z", "Section");
+        assert_eq!(document.get("added", "section"), Some("z"));
+        assert_eq!(document.get("KEY", "Section"), Some("Value"));
+    }
+
+    #[test]
+    fn merging() {
+        let mut document = IniParser::new().name_case(NameCase::Lower).parse("[section]\nkey = 1").unwrap();
+        document.set_case_index(true);
+        let other = IniDocument::from_string("[Section]\nKey = 2\nOther = 3").unwrap();
+        document.merge_section(&other, "Section", MergeStrategy::KeepExisting);
+        assert_eq!(document.get("key", "section"), Some("1"));
+        assert_eq!(document.get("other", "section"), Some("3"));
+        assert_eq!(document.get_section("Section"), None);
+        assert_eq!(document.get_case_insensitive("OTHER", "SECTION"), Some("3"));
+
+        let merged = IniDocument::merge_all_with_priority(&[&document, &other], &MergePriority::new());
+        assert_eq!(merged.name_case(), NameCase::Lower);
+        assert!(merged.has_case_index());
+        assert_eq!(merged.get("key", "section"), Some("2"));
+        assert_eq!(merged.get_case_insensitive("KEY", "SECTION"), Some("2"));
+    }
+
+    #[test]
+    fn ci_remove() {
+        let ini = r"FOO = bar
+[Section1]
+foo = baz";
+        let mut document = IniDocument::from_string(ini).unwrap();
+        assert_eq!(document.remove_case_insensitive("foo", ""), Some("bar".into()));
+        assert_eq!(document.get("FOO", ""), None);
+        assert!(document.remove_section_case_insensitive("SECTION1").is_some());
+        assert_eq!(document.get_section("Section1"), None);
+    }
+
+    #[test]
+    fn separate_sections_and_keys() {
+        let ini = r"[Section]
+Key = first
+[section]
+key = second
+[SECTION]
+Key = third";
+        let parser = IniParser::new().case_insensitivity(CaseInsensitivity { sections: true, keys: false });
+        let document = parser.parse(ini).unwrap();
+        assert_eq!(document.section_count(), 1);
+        assert_eq!(document.get("Key", "Section"), Some("third"));
+        assert_eq!(document.get("key", "Section"), Some("second"));
+        assert_eq!(document.get_case_insensitive("Key", "section"), Some("third"));
+        assert_eq!(document.get_case_insensitive("KEY", "section"), None);
+
+        let parser = IniParser::new().case_insensitivity(CaseInsensitivity { sections: false, keys: true });
+        let document = parser.parse(ini).unwrap();
+        assert_eq!(document.section_count(), 3);
+        assert_eq!(document.get("Key", "SECTION"), Some("third"));
+        assert_eq!(document.get_case_insensitive("KEY", "section"), Some("second"));
+        assert_eq!(document.get_case_insensitive("key", "Section2"), None);
+        assert_eq!(document.get_case_insensitive("key", "sEcTiOn"), None);
+
+        let mut document = IniParser::new().parse(ini).unwrap();
+        assert_eq!(document.section_count(), 3);
+        document.set_case_insensitivity(CaseInsensitivity { sections: true, keys: false });
+        document.set_case_index(true);
+        assert_eq!(document.get_case_insensitive("KEY", "Section"), None);
+        assert_eq!(document.get_case_insensitive("key", "section"), Some("second"));
+    }
+
+    #[test]
+    fn equality() {
+        let mut a = IniDocument::from_string("[Section]\nKey = value\nother = thing").unwrap();
+        let b = IniDocument::from_string("[section]\nOTHER = thing\nkey = value").unwrap();
+        assert_ne!(a, b);
+        assert!(a.eq_case_insensitive(&b));
+        assert!(!a.eq_case_insensitive(&IniDocument::from_string("[section]\nkey = VALUE\nother = thing").unwrap()));
+
+        a.set_case_insensitivity(CaseInsensitivity { sections: true, keys: false });
+        assert!(!a.eq_case_insensitive(&b));
+        a.set_case_insensitivity(CaseInsensitivity::default());
+
+        // settings aren't part of the contents
+        let mut c = a.clone();
+        c.set_case_index(true);
+        c.set_case_folding(CaseFolding::Unicode);
+        assert_eq!(a, c);
+    }
+
+    #[test]
+    fn folding() {
+        let ini = "CAFÉ = latte\n\u{130} = dotted\nI = plain";
+        let mut document = IniDocument::from_string(ini).unwrap();
+
+        assert_eq!(document.case_folding(), CaseFolding::Ascii);
+        assert_eq!(document.get_case_insensitive("café", ""), None);
+        assert_eq!(document.get_case_insensitive("i\u{307}", ""), None);
+        assert_eq!(document.get_case_insensitive("i", ""), Some("plain"));
+
+        document.set_case_folding(CaseFolding::Unicode);
+        assert_eq!(document.get_case_insensitive("café", ""), Some("latte"));
+        assert_eq!(document.get_case_insensitive("i\u{307}", ""), Some("dotted"));
+        assert_eq!(document.get_case_insensitive("i", ""), Some("plain"));
+    }
+
+    #[test]
+    fn index() {
+        let mut document = IniDocument::empty();
+        for i in 0..1000 {
+            document.insert(format!("Key{}", i), i.to_string(), format!("Section{}", i % 10));
+        }
+
+        assert_eq!(document.get_case_insensitive("KEY999", "section9"), Some("999"));
+
+        document.set_case_index(true);
+        for i in 0..1000 {
+            let value = i.to_string();
+            assert_eq!(document.get_case_insensitive(format!("key{}", i), format!("SECTION{}", i % 10)), Some(value.as_str()));
+        }
+        // lookups go through the index instead of comparing names, so a name missing from it can't be found
+        let index = document.case_index.as_mut().unwrap();
+        assert_eq!(index.keys["Section9"]["key999"], ["Key999"]);
+        index.keys.get_mut("Section9").unwrap().remove("key999");
+        assert_eq!(document.get_case_insensitive("KEY999", "section9"), None);
+        assert_eq!(document.get("Key999", "Section9"), Some("999"));
+        document.set_case_index(true);
+        assert_eq!(document.get_case_insensitive("KEY999", "section9"), Some("999"));
+
+        // the index follows changes to the document
+        document.insert("NewKey", "new", "NewSection");
+        assert_eq!(document.get_case_insensitive("newkey", "newsection"), Some("new"));
+        assert_eq!(document.remove_case_insensitive("KEY0", "section0"), Some("0".into()));
+        assert_eq!(document.get_case_insensitive("key0", "section0"), None);
+        document.rename_section("Section1", "Other").unwrap();
+        assert_eq!(document.get_case_insensitive("key1", "section1"), None);
+        assert_eq!(document.get_case_insensitive("key1", "other"), Some("1"));
+        document.rename_key("Key11", "Renamed", "Other");
+        assert_eq!(document.get_case_insensitive("renamed", "OTHER"), Some("11"));
+        document.clear();
+        assert_eq!(document.get_case_insensitive("key2", "section2"), None);
+
+        // and the original names are still used for output
+        document.insert("MixedCase", "v", "");
+        assert!(document.to_string().contains("MixedCase"));
+    }
+}
@@ -0,0 +1,83 @@
+//! Rendering parse errors as diagnostics, with the `miette` feature.
+
+use super::*;
+use miette::{Diagnostic, LabeledSpan};
+
+/// Errors from parsing label the offending line with their [`span`](InnitError::span),
+/// so a [`miette::Report`] with the input attached as its source code can point at it.
+/// 
+/// ```
+/// # use innit::*;
+/// let ini = "foo = bar\nbeans\nbaz = bop";
+/// let err = IniDocument::from_string(ini).unwrap_err();
+/// let report = miette::Report::new(err).with_source_code(ini);
+/// assert_eq!(report.labels().unwrap().next().unwrap().offset(), 10);
+/// ```
+impl Diagnostic for InnitError {
+    fn code<'a>(&'a self) -> Option<Box<dyn fmt::Display + 'a>> {
+        Some(Box::new(format!("innit::{}", self.kind())))
+    }
+    fn labels(&self) -> Option<Box<dyn Iterator<Item = LabeledSpan> + '_>> {
+        let span = self.span()?;
+        let label = LabeledSpan::new_primary_with_span(Some(self.label().into()), span);
+        Some(Box::new(std::iter::once(label)))
+    }
+}
+
+impl InnitError {
+    /// a short name for the kind of error, for its diagnostic code
+    fn kind(&self) -> &'static str {
+        match self {
+            InnitError::MissingEquals(..) => "missing_equals",
+            InnitError::EmptyStringSection(..) => "empty_section_name",
+            InnitError::KeyOutsideSection { .. } => "key_outside_section",
+            InnitError::MalformedSection { .. } => "malformed_section",
+            InnitError::DuplicateSection { .. } => "duplicate_section",
+            InnitError::TooManySections { .. } => "too_many_sections",
+            InnitError::TooManyKeys { .. } => "too_many_keys",
+            InnitError::LineTooLong { .. } => "line_too_long",
+            InnitError::InvalidCharacter { .. } => "invalid_character",
+            InnitError::EmptyValue { .. } => "empty_value",
+            _ => "error"
+        }
+    }
+    /// what to say next to the offending line
+    fn label(&self) -> &'static str {
+        match self {
+            InnitError::MissingEquals(..) => "no delimiter on this line",
+            InnitError::EmptyStringSection(..) => "section with no name",
+            InnitError::KeyOutsideSection { .. } => "key before the first section header",
+            InnitError::MalformedSection { .. } => "unmatched bracket",
+            InnitError::DuplicateSection { .. } => "section already had a header",
+            InnitError::TooManySections { .. } => "one section too many",
+            InnitError::TooManyKeys { .. } => "one key too many",
+            InnitError::LineTooLong { .. } => "line is too long",
+            InnitError::InvalidCharacter { .. } => "control character in this line",
+            InnitError::EmptyValue { .. } => "key with no value",
+            _ => "here"
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn labels() {
+        let ini = "foo = bar\n[section1]\nbaz = bop\n[section1\nqux = quux";
+        let err = IniDocument::from_string(ini).unwrap_err();
+        let labels: Vec<LabeledSpan> = err.labels().unwrap().collect();
+        assert_eq!(labels.len(), 1);
+        let span = labels[0].inner();
+        assert_eq!(&ini[span.offset()..span.offset() + span.len()], "[section1");
+        assert_eq!(labels[0].label(), Some("unmatched bracket"));
+        assert_eq!(err.code().unwrap().to_string(), "innit::malformed_section");
+
+        let err = IniParser::new().require_sections(true).parse("\u{FEFF}foo = bar").unwrap_err();
+        let span = err.labels().unwrap().next().unwrap();
+        assert_eq!((span.offset(), span.len()), (3, 9));
+
+        assert!(InnitError::EmptySectionName.labels().is_none());
+    }
+}
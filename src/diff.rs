@@ -0,0 +1,120 @@
+//! Comparing documents.
+
+use super::*;
+
+/// A single difference between two documents, as found by [`IniDocument::diff`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Change {
+    /// A key exists in the new document but not the old one.
+    Added {
+        /// The section the key is in.
+        section: String,
+        /// The key.
+        key: String,
+        /// The value in the new document.
+        value: String,
+    },
+    /// A key exists in the old document but not the new one.
+    Removed {
+        /// The section the key was in.
+        section: String,
+        /// The key.
+        key: String,
+        /// The value in the old document.
+        value: String,
+    },
+    /// A key exists in both documents, with different values.
+    Changed {
+        /// The section the key is in.
+        section: String,
+        /// The key.
+        key: String,
+        /// The value in the old document.
+        old: String,
+        /// The value in the new document.
+        new: String,
+    },
+}
+
+impl IniDocument {
+    /// Find the differences between this document and another, treating this one as the old version and `other` as the new one.
+    /// 
+    /// Only keys and values are compared, so sections without any keys, comments and array keys are ignored.
+    /// Changes are sorted by section, then by key.
+    pub fn diff(&self, other: &IniDocument) -> Vec<Change> {
+        let mut changes = Vec::new();
+
+        for (name, data) in &self.sections {
+            for (k, v) in &data.0 {
+                match other.get(k, name) {
+                    None => changes.push(Change::Removed { section: name.clone(), key: k.clone(), value: v.clone() }),
+                    Some(new) if new != v => changes.push(Change::Changed { section: name.clone(), key: k.clone(), old: v.clone(), new: new.into() }),
+                    Some(_) => ()
+                }
+            }
+        }
+        for (name, data) in &other.sections {
+            for (k, v) in &data.0 {
+                if self.get(k, name).is_none() {
+                    changes.push(Change::Added { section: name.clone(), key: k.clone(), value: v.clone() })
+                }
+            }
+        }
+
+        changes.sort_by(|a, b| a.position().cmp(&b.position()));
+        changes
+    }
+}
+
+impl Change {
+    /// section and key, for sorting
+    fn position(&self) -> (&str, &str) {
+        match self {
+            Change::Added { section, key, .. } | Change::Removed { section, key, .. } | Change::Changed { section, key, .. } => (section, key)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn clone() {
+        let ini = r"foo = bar
+[section1]
+foo = baz";
+        let original = IniDocument::from_string(ini).unwrap();
+        let mut copy = original.clone();
+        assert_eq!(copy, original);
+
+        copy.insert("foo", "changed", "section1");
+        copy.remove_section("");
+        assert_eq!(original.get("foo", "section1"), Some("baz"));
+        assert_eq!(original.get("foo", ""), Some("bar"));
+    }
+
+    #[test]
+    fn diff() {
+        let ini = r"foo = bar
+[section1]
+foo = baz
+baz = bop";
+        let original = IniDocument::from_string(ini).unwrap();
+        let mut changed = original.clone();
+        assert_eq!(original.diff(&changed), vec![]);
+
+        changed.insert("foo", "qux", "section1");
+        assert_eq!(original.diff(&changed), vec![
+            Change::Changed { section: "section1".into(), key: "foo".into(), old: "baz".into(), new: "qux".into() }
+        ]);
+
+        changed.remove("foo", "");
+        changed.insert("new", "value", "section2");
+        assert_eq!(original.diff(&changed), vec![
+            Change::Removed { section: "".into(), key: "foo".into(), value: "bar".into() },
+            Change::Changed { section: "section1".into(), key: "foo".into(), old: "baz".into(), new: "qux".into() },
+            Change::Added { section: "section2".into(), key: "new".into(), value: "value".into() },
+        ]);
+    }
+}
@@ -0,0 +1,158 @@
+//! Converting documents to and from JSON value trees, with the `json` feature.
+
+use super::*;
+use serde_json::{Map as JsonMap, Value};
+
+impl IniDocument {
+    /// Convert the document into a JSON object, with the keys of the unnamed section at the top level,
+    /// and each named section as an object of its own. Array keys become arrays of strings.
+    /// 
+    /// A named section with the same name as a key in the unnamed section replaces that key, since they can't both be written.
+    /// Comments aren't kept.
+    /// 
+    /// ```
+    /// # use innit::*;
+    /// let document = IniDocument::from_string("foo = bar\n[section1]\nfoo = baz").unwrap();
+    /// let value = document.to_json_value();
+    /// assert_eq!(value["foo"], "bar");
+    /// assert_eq!(value["section1"]["foo"], "baz");
+    /// ```
+    pub fn to_json_value(&self) -> Value {
+        let mut ret = self.section_json(&self.global_section);
+        for name in self.sections.keys().filter(|name| **name != self.global_section) {
+            ret.insert(name.clone(), Value::Object(self.section_json(name)));
+        }
+        Value::Object(ret)
+    }
+    /// Create a document from a JSON object, the reverse of [`to_json_value`](IniDocument::to_json_value).
+    /// 
+    /// Strings, numbers and booleans at the top level go in the unnamed section, and objects become named sections,
+    /// which can only contain strings, numbers and booleans themselves. Numbers and booleans are stored as they're written in JSON.
+    /// Arrays of them, at either level, become array keys. Anything else, like objects nested more than two levels deep
+    /// or `null`, is an [`InnitError::UnsupportedJson`].
+    /// 
+    /// ```
+    /// # use innit::*;
+    /// let value = serde_json::json!({ "foo": "bar", "section1": { "port": 8080 } });
+    /// let document = IniDocument::from_json_value(&value).unwrap();
+    /// assert_eq!(document.get("port", "section1"), Some("8080"));
+    /// 
+    /// let nested = serde_json::json!({ "section1": { "too": { "deep": "!" } } });
+    /// assert!(IniDocument::from_json_value(&nested).is_err());
+    /// ```
+    pub fn from_json_value(value: &Value) -> Result<IniDocument, InnitError> {
+        let top = match value {
+            Value::Object(top) => top,
+            _ => return Err(unsupported("", value))
+        };
+        let mut document = IniDocument::empty();
+        for (k, v) in top {
+            match v {
+                Value::Object(section) => {
+                    document.insert_section(k.as_str(), std::iter::empty::<(String, String)>());
+                    for (key, v) in section {
+                        document.insert_json(key, v, k, &format!("{}.{}", k, key))?
+                    }
+                }
+                _ => document.insert_json(k, v, "", k)?
+            }
+        }
+        Ok(document)
+    }
+
+    fn section_json(&self, section: &str) -> JsonMap<String, Value> {
+        let mut ret = JsonMap::new();
+        if let Some(s) = self.sections.get(section) {
+            ret.extend(s.iter().map(|(k, v)| (k.to_string(), Value::String(v.into()))));
+        }
+        if let Some(arrays) = self.section_arrays(section) {
+            ret.extend(arrays.iter().map(|(k, values)| (k.clone(), values.iter().cloned().map(Value::String).collect())));
+        }
+        ret
+    }
+    /// insert a single key, or an array key if it's an array
+    fn insert_json(&mut self, key: &str, value: &Value, section: &str, path: &str) -> Result<(), InnitError> {
+        match value {
+            Value::Array(values) => {
+                let values = values.iter().enumerate()
+                    .map(|(i, v)| json_scalar(v).ok_or_else(|| unsupported(&format!("{}[{}]", path, i), v)))
+                    .collect::<Result<Vec<String>, InnitError>>()?;
+                for v in values {
+                    self.push(key, v, section)
+                }
+                Ok(())
+            }
+            _ => {
+                let value = json_scalar(value).ok_or_else(|| unsupported(path, value))?;
+                self.insert(key, value, section);
+                Ok(())
+            }
+        }
+    }
+}
+
+/// the text of a value that can be stored as it is
+fn json_scalar(value: &Value) -> Option<String> {
+    match value {
+        Value::String(s) => Some(s.clone()),
+        Value::Number(n) => Some(n.to_string()),
+        Value::Bool(b) => Some(b.to_string()),
+        _ => None
+    }
+}
+fn unsupported(path: &str, value: &Value) -> InnitError {
+    let found = match value {
+        Value::Null => "null",
+        Value::Array(_) => "an array nested inside an array",
+        Value::Object(_) if path.contains('.') || path.contains('[') => "an object nested more than two levels deep",
+        Value::Object(_) => "an object",
+        _ => "not an object"
+    };
+    InnitError::UnsupportedJson { path: path.into(), found }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn round_trip() {
+        let ini = "foo = bar\nitems[] = a\nitems[] = b\n[section1]\nfoo = baz\nport = 8080";
+        let document = IniParser::new().array_keys(true).parse(ini).unwrap();
+        let value = document.to_json_value();
+        assert_eq!(value, json!({
+            "foo": "bar",
+            "items": ["a", "b"],
+            "section1": { "foo": "baz", "port": "8080" },
+        }));
+        assert_eq!(IniDocument::from_json_value(&value).unwrap(), document);
+
+        let document = IniDocument::from_json_value(&json!({ "empty": {} })).unwrap();
+        assert_eq!(document.key_count("empty"), Some(0));
+        assert_eq!(document.to_json_value(), json!({ "empty": {} }));
+
+        let value = json!({ "debug": true, "section1": { "port": 8080, "ratio": 0.5, "hosts": ["a", 1] } });
+        let document = IniDocument::from_json_value(&value).unwrap();
+        assert_eq!(document.get("debug", ""), Some("true"));
+        assert_eq!(document.get("port", "section1"), Some("8080"));
+        assert_eq!(document.get("ratio", "section1"), Some("0.5"));
+        assert_eq!(document.get_all("hosts", "section1"), Some(&["a".to_string(), "1".to_string()][..]));
+    }
+
+    #[test]
+    fn unsupported() {
+        let err = IniDocument::from_json_value(&json!({ "section1": { "too": { "deep": "!" } } })).unwrap_err();
+        assert_eq!(err, InnitError::UnsupportedJson { path: "section1.too".into(), found: "an object nested more than two levels deep" });
+        assert_eq!(err.to_string(), "JSON value at `section1.too` is an object nested more than two levels deep, which can't be stored in an INI document");
+
+        let err = IniDocument::from_json_value(&json!({ "foo": null })).unwrap_err();
+        assert_eq!(err, InnitError::UnsupportedJson { path: "foo".into(), found: "null" });
+        let err = IniDocument::from_json_value(&json!({ "foo": [[1]] })).unwrap_err();
+        assert_eq!(err, InnitError::UnsupportedJson { path: "foo[0]".into(), found: "an array nested inside an array" });
+        let err = IniDocument::from_json_value(&json!({ "foo": [{}] })).unwrap_err();
+        assert_eq!(err, InnitError::UnsupportedJson { path: "foo[0]".into(), found: "an object nested more than two levels deep" });
+        let err = IniDocument::from_json_value(&json!("foo")).unwrap_err();
+        assert_eq!(err, InnitError::UnsupportedJson { path: "".into(), found: "not an object" });
+    }
+}
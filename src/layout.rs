@@ -0,0 +1,561 @@
+//! Keeping the original text of parsed documents, so they can be written back out with unchanged lines kept as they were.
+
+use super::*;
+use std::borrow::Cow;
+use std::collections::HashSet;
+
+/// The original lines of a document parsed with [`IniParser::preserve_formatting`] or [`IniParser::preserve_comments`], or nothing for other documents.
+#[derive(Debug, Clone, Default)]
+pub(crate) struct Layout {
+    bom: bool,
+    /// only comments and blank lines are kept as they were, and everything else is tidied up
+    normalize: bool,
+    /// regenerated lines keep the indentation of the lines they replace
+    indent: bool,
+    /// the document was parsed with quoted keys or values, so regenerated lines quote them too
+    quoted_keys: bool,
+    quoted_values: bool,
+    lines: Vec<RawLine>,
+}
+
+/// a line as it was written, including any `\r`, and what it meant
+#[derive(Debug, Clone)]
+struct RawLine {
+    text: String,
+    kind: LineKind,
+}
+#[derive(Debug, Clone)]
+enum LineKind {
+    /// blank lines, and anything else the document doesn't keep
+    Other,
+    Comment,
+    Section(String),
+    Key {
+        section: String,
+        key: String,
+        value: String,
+        comment: Option<String>,
+        delimiter: char,
+        value_start: usize,
+    },
+    ArrayItem {
+        section: String,
+        key: String,
+        value: String,
+    },
+}
+
+/// what the parser made of a line
+pub(crate) enum Parsed<'a> {
+    Other,
+    Comment,
+    Section,
+    /// the key, the delimiter between it and its value, and the byte offset of the value within the line
+    Key(Cow<'a, str>, char, usize),
+    ArrayItem(Cow<'a, str>),
+}
+
+impl Layout {
+    pub(crate) fn new(bom: bool, normalize: bool, indent: bool) -> Layout {
+        Layout { bom, normalize, indent, lines: Vec::new(), ..Default::default() }
+    }
+    /// quote keys and values in regenerated lines the same way the parser unquoted them
+    pub(crate) fn with_quoting(mut self, keys: bool, values: bool) -> Layout {
+        self.quoted_keys = keys;
+        self.quoted_values = values;
+        self
+    }
+    /// forget the original lines, but keep how the document was quoted
+    pub(crate) fn forget_lines(&mut self) {
+        *self = Layout::default().with_quoting(self.quoted_keys, self.quoted_values)
+    }
+    /// whether there's anything to follow, since every parsed document has at least one line
+    pub(crate) fn is_kept(&self) -> bool {
+        !self.lines.is_empty()
+    }
+    /// remember a line, after it's been parsed into the document
+    pub(crate) fn record(&mut self, document: &IniDocument, text: &str, parsed: Parsed, section: &str) {
+        let kind = match parsed {
+            Parsed::Other => LineKind::Other,
+            Parsed::Comment => LineKind::Comment,
+            Parsed::Section => LineKind::Section(section.into()),
+            Parsed::Key(key, delimiter, value_start) => LineKind::Key {
+                section: section.into(),
+                delimiter,
+                value_start,
+                value: document.get(key.as_ref(), section).unwrap_or_default().into(),
+                comment: document.get_comment(key.as_ref(), section).map(Into::into),
+                key: key.into_owned(),
+            },
+            Parsed::ArrayItem(key) => LineKind::ArrayItem {
+                section: section.into(),
+                value: document.get_all(key.as_ref(), section).and_then(|a| a.last()).cloned().unwrap_or_default(),
+                key: key.into_owned(),
+            },
+        };
+        self.lines.push(RawLine { text: text.into(), kind })
+    }
+    /// the value of a key exactly as it was written, if it was parsed from a line and hasn't changed since
+    pub(crate) fn raw_value<'a>(&'a self, document: &IniDocument, key: &str, section: &str) -> Option<&'a str> {
+        // a key set more than once gets its value from the last line
+        let (text, value, value_start) = self.lines.iter().rev().find_map(|line| match &line.kind {
+            LineKind::Key { section: s, key: k, value, value_start, .. } if s == section && k == key => Some((&line.text, value, *value_start)),
+            _ => None
+        })?;
+        if document.get(key, section) != Some(value.as_str()) {
+            return None
+        }
+        let text = text.strip_suffix('\r').unwrap_or(text);
+        text.get(value_start..)
+    }
+}
+
+impl IniDocument {
+    /// the options the document is written with when there's no layout to follow: quoted the same way it was parsed,
+    /// and with values quoted anyway if any have newlines, since they'd split over several lines otherwise.
+    /// a carriage return on its own doesn't end a line, so it can be written as it is
+    pub(crate) fn display_options(&self) -> FormatOptions {
+        FormatOptions {
+            quoted_keys: self.layout.quoted_keys,
+            quoted_values: self.layout.quoted_values || self.value_with_line_break(&['\n']).is_some(),
+            ..Default::default()
+        }
+    }
+    /// write the document out following its layout, only regenerating lines that have changed
+    pub(crate) fn write_with_layout(&self, layout: &Layout) -> String {
+        let mut writer = LayoutWriter::new(self, layout);
+        // an empty last line means the document ended with a newline, which should stay at the very end
+        let mut lines = layout.lines.as_slice();
+        let trailing = match lines.split_last() {
+            Some((last, rest)) if last.text.is_empty() && !rest.is_empty() => {
+                lines = rest;
+                true
+            }
+            _ => false
+        };
+        for (i, line) in lines.iter().enumerate() {
+            writer.line(i, line)
+        }
+        let mut out = writer.finish();
+        if trailing {
+            out.push(Cow::Borrowed(""))
+        }
+        let bom = if layout.bom { "\u{FEFF}" } else { "" };
+        format!("{}{}", bom, out.join("\n"))
+    }
+}
+
+/// the state of writing a document out following its layout
+struct LayoutWriter<'a> {
+    document: &'a IniDocument,
+    /// `\r` if the document used `\r\n` line endings, for the end of regenerated lines
+    cr: &'static str,
+    normalize: bool,
+    indent: bool,
+    /// how keys and values in regenerated lines are written
+    options: FormatOptions,
+    /// the line each key was last set on, since that's the only one that counts
+    last_set: HashMap<(&'a str, &'a str), usize>,
+    /// the values each array key was parsed with
+    parsed_arrays: HashMap<(&'a str, &'a str), Vec<&'a str>>,
+    written_arrays: HashSet<(&'a str, &'a str)>,
+    /// sections that had keys when the document was parsed
+    parsed_sections: HashSet<&'a str>,
+    /// sections that have had their new keys written
+    finished: HashSet<&'a str>,
+    out: Vec<Cow<'a, str>>,
+    /// comment lines that haven't been written yet, since they depend on the key below them
+    pending: Vec<Cow<'a, str>>,
+    section: &'a str,
+    /// the indentation of the last key in the current section, for new keys to follow
+    key_indent: &'a str,
+    /// where the current section starts in `out`
+    block_start: usize,
+    /// the current section has been removed from the document, so its lines are skipped
+    skipping: bool,
+}
+impl<'a> LayoutWriter<'a> {
+    fn new(document: &'a IniDocument, layout: &'a Layout) -> LayoutWriter<'a> {
+        let mut last_set = HashMap::new();
+        let mut parsed_arrays: HashMap<_, Vec<_>> = HashMap::new();
+        let mut parsed_sections = HashSet::new();
+        for (i, line) in layout.lines.iter().enumerate() {
+            match &line.kind {
+                LineKind::Key { section, key, .. } => {
+                    last_set.insert((section.as_str(), key.as_str()), i);
+                    parsed_sections.insert(section.as_str());
+                }
+                LineKind::ArrayItem { section, key, value } => {
+                    parsed_arrays.entry((section.as_str(), key.as_str())).or_default().push(value.as_str());
+                    parsed_sections.insert(section.as_str());
+                }
+                _ => ()
+            }
+        }
+        let cr = match layout.lines.first() {
+            Some(line) if line.text.ends_with('\r') => "\r",
+            _ => ""
+        };
+        LayoutWriter {
+            document, cr, last_set, parsed_arrays, parsed_sections,
+            normalize: layout.normalize,
+            indent: layout.indent,
+            options: FormatOptions { sorted: true, line_ending: LineEnding::Lf, ..document.display_options() },
+            written_arrays: HashSet::new(),
+            finished: HashSet::new(),
+            out: Vec::new(),
+            pending: Vec::new(),
+            section: &document.global_section,
+            key_indent: "",
+            block_start: 0,
+            skipping: false,
+        }
+    }
+
+    fn line(&mut self, i: usize, line: &'a RawLine) {
+        let document = self.document;
+        match &line.kind {
+            LineKind::Section(name) => {
+                self.end_section();
+                self.section = name;
+                self.key_indent = "";
+                self.block_start = self.out.len();
+                // headers on their own don't make a section, so only skip ones that had keys and don't any more
+                self.skipping = self.parsed_sections.contains(name.as_str()) && !document.sections.contains_key(name.as_str());
+                if !self.skipping {
+                    self.out.push(self.kept(line))
+                }
+            }
+            _ if self.skipping => (),
+            LineKind::Comment => self.pending.push(self.kept(line)),
+            LineKind::Key { section, key, value, comment, delimiter, .. } => {
+                let indent = self.indentation(line);
+                self.key_indent = indent;
+                let current = match document.get(key, section) {
+                    Some(current) => current,
+                    None => {
+                        // the comment goes with the key
+                        self.pending.clear();
+                        return
+                    }
+                };
+                if self.last_set[&(section.as_str(), key.as_str())] != i {
+                    // overridden by a later line, so it can stay as it is
+                    self.flush();
+                    self.out.push(self.kept(line));
+                    return
+                }
+                let current_comment = document.get_comment(key, section);
+                if current_comment == comment.as_deref() {
+                    self.flush()
+                }
+                else {
+                    self.pending.clear();
+                    for c in current_comment.into_iter().flat_map(|c| c.split('\n')) {
+                        self.push_generated(format!("{}# {}", indent, c))
+                    }
+                }
+                if current == value {
+                    self.out.push(self.kept(line))
+                }
+                else {
+                    let text = format!("{}{}{}", written_key(key, &self.options), Spacing::default().around(*delimiter), written_value(section, key, current, &self.options));
+                    self.push_generated(format!("{}{}", indent, text))
+                }
+            }
+            LineKind::ArrayItem { section, key, .. } => {
+                let indent = self.indentation(line);
+                self.key_indent = indent;
+                self.flush();
+                let id = (section.as_str(), key.as_str());
+                let current = document.get_all(key, section).unwrap_or_default();
+                if current.iter().map(|v| v.as_str()).eq(self.parsed_arrays[&id].iter().copied()) {
+                    self.out.push(self.kept(line))
+                }
+                else if self.written_arrays.insert(id) {
+                    // the whole array is written where it started
+                    for v in current {
+                        let text = format!("{}[]{}{}", written_key(key, &self.options), Spacing::default().equals(), written_value(section, key, v, &self.options));
+                        self.push_generated(format!("{}{}", indent, text))
+                    }
+                }
+            }
+            LineKind::Other => {
+                self.flush();
+                self.out.push(self.kept(line))
+            }
+        }
+    }
+
+    /// write any new sections, and return all the lines
+    fn finish(mut self) -> Vec<Cow<'a, str>> {
+        self.end_section();
+
+        let document = self.document;
+        let mut names: Vec<&String> = document.sections.keys().filter(|name| !self.finished.contains(name.as_str())).collect();
+        names.sort();
+        for name in names {
+            // the empty string can't be a header, and only stands for the global section
+            if name != "" {
+                self.push_generated(format!("[{}]", escape_section_name(name)))
+            }
+            let text = fmt_section(name, &document.sections[name], document.meta.get(name), document.section_arrays(name), &self.options);
+            for line in text.lines() {
+                self.push_generated(line.into())
+            }
+        }
+        self.out
+    }
+
+    /// write the keys that have been added to the current section, after its last line that isn't blank
+    fn end_section(&mut self) {
+        self.flush();
+        if self.skipping || !self.finished.insert(self.section) {
+            return
+        }
+        let document = self.document;
+        let section = self.section;
+        let data = match document.sections.get(section) {
+            Some(data) => data,
+            None => return
+        };
+        let new: SectionMap = data.0.iter()
+            .filter(|(k, _)| !self.last_set.contains_key(&(section, k.as_str())))
+            .map(|(k, v)| (k.clone(), v.clone()))
+            .collect();
+        let arrays: Map<String, Vec<String>> = document.section_arrays(section).into_iter().flatten()
+            .filter(|(k, _)| !self.parsed_arrays.contains_key(&(section, k.as_str())))
+            .map(|(k, v)| (k.clone(), v.clone()))
+            .collect();
+        let text = fmt_section(section, &Section(new), document.meta.get(section), Some(&arrays), &self.options);
+
+        let at = self.out[self.block_start..].iter().rposition(|l| !l.trim().is_empty()).map_or(self.block_start, |i| self.block_start + i + 1);
+        let lines: Vec<Cow<str>> = text.lines().map(|l| Cow::Owned(format!("{}{}{}", self.key_indent, l, self.cr))).collect();
+        self.out.splice(at..at, lines);
+    }
+
+    /// a line that hasn't changed, exactly as it was, or tidied up if only comments are being kept
+    fn kept(&self, line: &'a RawLine) -> Cow<'a, str> {
+        if !self.normalize {
+            return Cow::Borrowed(&line.text)
+        }
+        let text = match &line.kind {
+            LineKind::Section(name) => format!("[{}]", escape_section_name(name)),
+            LineKind::Key { section, key, value, delimiter, .. } => {
+                format!("{}{}{}", written_key(key, &self.options), Spacing::default().around(*delimiter), written_value(section, key, value, &self.options))
+            }
+            LineKind::ArrayItem { section, key, value } => {
+                format!("{}[]{}{}", written_key(key, &self.options), Spacing::default().equals(), written_value(section, key, value, &self.options))
+            }
+            LineKind::Comment | LineKind::Other => line.text.trim().into(),
+        };
+        // blank lines don't get any, so they don't end up with trailing whitespace
+        let indent = if text.is_empty() { "" } else { self.indentation(line) };
+        Cow::Owned(format!("{}{}{}", indent, text, self.cr))
+    }
+    /// the whitespace at the start of a line, if it's being kept
+    fn indentation(&self, line: &'a RawLine) -> &'a str {
+        if !self.indent {
+            return ""
+        }
+        let text = line.text.as_str();
+        &text[..text.len() - text.trim_start().len()]
+    }
+    /// write out any comment lines that were being held back
+    fn flush(&mut self) {
+        self.out.append(&mut self.pending)
+    }
+    fn push_generated(&mut self, line: String) {
+        self.out.push(Cow::Owned(format!("{}{}", line, self.cr)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const INI: &str = "; settings for the thing\r
+  name   =  thing  \r
+\r
+# the port\r
+port=80\r
+\r
+[paths]   \r
+home = /home/thing\r
+   # indented comment\r
+tmp =/tmp\r
+\r
+[removed]\r
+gone = yes\r
+";
+
+    #[test]
+    fn unchanged() {
+        let parser = IniParser::new().preserve_formatting(true);
+        let document = parser.parse(INI).unwrap();
+        assert_eq!(document.to_string(), INI);
+        assert_eq!(document, IniParser::new().parse(INI).unwrap());
+
+        let with_bom = format!("\u{FEFF}{}", INI.trim_end());
+        assert_eq!(parser.parse(&with_bom).unwrap().to_string(), with_bom);
+
+        // a header with no keys doesn't make a section, but should still be kept
+        let empty = "[empty]\n\n[full]\na = b\n";
+        assert_eq!(parser.parse(empty).unwrap().to_string(), empty);
+    }
+
+    #[test]
+    fn change_one_key() {
+        let mut document = IniParser::new().preserve_formatting(true).parse(INI).unwrap();
+        document.insert("port", "8080", "");
+        assert_eq!(document.to_string(), INI.replace("port=80\r", "port = 8080\r"));
+    }
+
+    #[test]
+    fn structural_changes() {
+        let mut document = IniParser::new().preserve_formatting(true).parse(INI).unwrap();
+        document.remove("home", "paths");
+        document.remove_section("removed");
+        document.insert("var", "/var", "paths");
+        document.set_comment("var", "paths", "new key");
+        document.set_comment("port", "", "the port number");
+        document.insert("foo", "bar", "new");
+        let expected = "; settings for the thing\r
+  name   =  thing  \r
+\r
+# the port number\r
+port=80\r
+\r
+[paths]   \r
+   # indented comment\r
+tmp =/tmp\r
+# new key\r
+var = /var\r
+\r
+[new]\r
+foo = bar\r
+";
+        assert_eq!(document.to_string(), expected);
+    }
+
+    #[test]
+    fn comments_only() {
+        let mut document = IniParser::new().preserve_comments(true).parse(INI).unwrap();
+        let expected = "; settings for the thing\r
+name = thing\r
+\r
+# the port\r
+port = 80\r
+\r
+[paths]\r
+home = /home/thing\r
+# indented comment\r
+tmp = /tmp\r
+\r
+[removed]\r
+gone = yes\r
+";
+        assert_eq!(document.to_string(), expected);
+
+        document.insert("port", "8080", "");
+        document.remove_section("removed");
+        assert_eq!(document.to_string(), expected.replace("80\r", "8080\r").replace("[removed]\r\ngone = yes\r\n", ""));
+
+        // full fidelity wins
+        let document = IniParser::new().preserve_comments(true).preserve_formatting(true).parse(INI).unwrap();
+        assert_eq!(document.to_string(), INI);
+    }
+
+    #[test]
+    fn mixed_delimiters() {
+        let ini = "a = 1\nb: 2\nc=3\n[section1]\nd : 4\n";
+        let parser = IniParser::new().delimiters(['=', ':']);
+        let mut document = parser.clone().preserve_formatting(true).parse(ini).unwrap();
+        assert_eq!(document.to_string(), ini);
+
+        for (key, section) in [("a", ""), ("b", ""), ("d", "section1")] {
+            document.insert(key, "changed", section);
+        }
+        document.insert("e", "new", "section1");
+        assert_eq!(document.to_string(), "a = changed\nb : changed\nc=3\n[section1]\nd : changed\ne = new\n");
+
+        // without the original formatting, there's nowhere to keep each line's delimiter
+        let document = parser.parse(ini).unwrap();
+        assert!(document.to_string_sorted().contains(&format!("b = 2{}", LINE_DELIM)));
+    }
+
+    #[test]
+    fn arrays_and_duplicates() {
+        let ini = "items[] = a\nitems[] = b\nkey = first\nkey = second\n";
+        let mut document = IniParser::new().preserve_formatting(true).array_keys(true).parse(ini).unwrap();
+        assert_eq!(document.to_string(), ini);
+        document.push("items", "c", "");
+        document.insert("key", "third", "");
+        assert_eq!(document.to_string(), "items[] = a\nitems[] = b\nitems[] = c\nkey = first\nkey = third\n");
+    }
+
+    #[test]
+    fn raw_values() {
+        let ini = "foo =  spaced out  \r\nquoted = \"a \\\"b\\\"\" \r\nfoo = again\r\n[section1]\nempty =\nbare\n";
+        let parser = IniParser::new().quoted_values(true).bare_keys(true);
+        let mut document = parser.clone().preserve_formatting(true).parse(ini).unwrap();
+        assert_eq!(document.get("quoted", ""), Some("a \"b\""));
+        assert_eq!(document.get_raw("quoted", ""), Some(" \"a \\\"b\\\"\" "));
+        assert_eq!(document.get("foo", ""), Some("again"));
+        assert_eq!(document.get_raw("foo", ""), Some(" again"));
+        assert_eq!(document.get_raw("empty", "section1"), Some(""));
+        assert_eq!(document.get_raw("bare", "section1"), Some(""));
+        assert_eq!(document.get_raw("missing", "section1"), None);
+
+        // changed and new keys fall back to their values
+        document.insert("foo", "changed", "");
+        document.insert("new", "value", "");
+        assert_eq!(document.get_raw("foo", ""), Some("changed"));
+        assert_eq!(document.get_raw("new", ""), Some("value"));
+
+        let document = parser.clone().preserve_comments(true).parse(ini).unwrap();
+        assert_eq!(document.get_raw("foo", ""), Some(" again"));
+        let document = parser.parse(ini).unwrap();
+        assert_eq!(document.get_raw("quoted", ""), Some("a \"b\""));
+    }
+
+    #[test]
+    fn quoting() {
+        let ini = "# c\na = \"x\"\nb = 1";
+        let parser = IniParser::new().quoted_values(true);
+        let mut document = parser.clone().preserve_formatting(true).parse(ini).unwrap();
+        document.insert("a", "line1\nline2", "");
+        document.insert("c", " padded ", "");
+        assert_eq!(document.to_string(), "# c\na = \"line1\\nline2\"\nb = 1\nc = \" padded \"");
+        assert_eq!(parser.parse(document.to_string()).unwrap(), document);
+
+        let document = parser.preserve_comments(true).parse("# c\na = \" x \"\n").unwrap();
+        assert_eq!(document.to_string(), "# c\na = \" x \"\n");
+    }
+
+    #[test]
+    fn indentation() {
+        let ini = "top = 1\n[server]\n    # the host\n    host = localhost\n\tport = 80\n    items[] = a\n\n[other]\n  x=y\n";
+        let parser = IniParser::new().array_keys(true).preserve_indentation(true);
+
+        let mut document = parser.clone().preserve_formatting(true).parse(ini).unwrap();
+        assert_eq!(document.to_string(), ini);
+        document.insert("host", "example.com", "server");
+        document.set_comment("host", "server", "the new host");
+        document.insert("port", "8080", "server");
+        document.push("items", "b", "server");
+        document.insert("new", "key", "server");
+        assert_eq!(
+            document.to_string(),
+            "top = 1\n[server]\n    # the new host\n    host = example.com\n\tport = 8080\n    items[] = a\n    items[] = b\n    new = key\n\n[other]\n  x=y\n"
+        );
+
+        let document = parser.clone().preserve_comments(true).parse(ini).unwrap();
+        assert_eq!(document.to_string(), "top = 1\n[server]\n    # the host\n    host = localhost\n\tport = 80\n    items[] = a\n\n[other]\n  x = y\n");
+
+        // without the option, only unchanged lines keep theirs
+        let mut document = IniParser::new().preserve_formatting(true).parse(ini).unwrap();
+        document.insert("port", "8080", "server");
+        assert!(document.to_string().contains("\nport = 8080\n"));
+    }
+}
@@ -39,32 +39,124 @@
 //! It ALSO also means that you don't need quotes or any quote escaping.
 //! See [the wikipedia page on INI](https://en.wikipedia.org/wiki/INI_file) for more info.
 //! 
+//! With the `sorted` feature, documents are stored in [`BTreeMap`](std::collections::BTreeMap)s instead of [`HashMap`]s,
+//! so sections and keys are always in sorted order, when written out and when iterated over.
+//! 
+//! The `json` feature adds conversions to and from [`serde_json::Value`]s, see [`IniDocument::to_json_value`].
+//! 
+//! Errors from parsing have the byte range of the offending line in the input, see [`InnitError::span`],
+//! and the `miette` feature implements [`miette::Diagnostic`] for them so that span is labelled when the error is reported.
+//! 
 //! innit is case sensitive by default, unlike the original MS-DOS and subsequent Windows implementations.
-//! The `case_insensitive` feature enables use of the case insensitive methods.
+//! The `case_insensitive` feature enables use of the case insensitive methods. Without it, they don't exist at all:
+//! 
+#![cfg_attr(not(feature = "case_insensitive"), doc = "```compile_fail")]
+#![cfg_attr(feature = "case_insensitive", doc = "```")]
+//! # use innit::*;
+//! let document = IniDocument::empty();
+//! assert_eq!(document.get_case_insensitive("FOO", ""), None);
+//! ```
 
 #![deny(missing_docs)]
 #![allow(clippy::comparison_to_empty)]
 
 use std::collections::HashMap;
+use std::fmt;
+use std::ops::Range;
 use thiserror::Error;
 
+mod array;
+mod borrowed;
+#[cfg(feature = "miette")]
+mod diagnostic;
+#[cfg(feature = "case_insensitive")]
+mod case;
+mod diff;
+#[cfg(feature = "json")]
+mod json;
+mod layout;
+mod merge;
+mod parser;
+mod schema;
+mod section;
+mod write;
+pub use borrowed::*;
+#[cfg(feature = "case_insensitive")]
+pub use case::*;
+pub use diff::*;
+pub use merge::*;
+pub use parser::*;
+pub use schema::*;
+pub use section::*;
+pub use write::*;
+use layout::{Layout, Parsed};
+
+/// The map type documents are stored in.
+/// 
+/// This is a [`HashMap`] by default, or a [`BTreeMap`](std::collections::BTreeMap) with the `sorted` feature.
+#[cfg(not(feature = "sorted"))]
+pub type Map<K, V> = HashMap<K, V>;
+/// The map type documents are stored in.
+/// 
+/// This is a [`HashMap`] by default, or a [`BTreeMap`](std::collections::BTreeMap) with the `sorted` feature.
+#[cfg(feature = "sorted")]
+pub type Map<K, V> = std::collections::BTreeMap<K, V>;
+/// The map the keys and values of a [`Section`] are stored in.
+pub type SectionMap = Map<String, String>;
+
+/// make a map with room for `capacity` entries, which does nothing for a `BTreeMap`
+#[cfg(not(feature = "sorted"))]
+pub(crate) fn map_with_capacity<K, V>(capacity: usize) -> Map<K, V> {
+    HashMap::with_capacity(capacity)
+}
+#[cfg(feature = "sorted")]
+pub(crate) fn map_with_capacity<K, V>(_capacity: usize) -> Map<K, V> {
+    Map::new()
+}
+
 /// A parsed or generated INI document.
 /// 
-/// Under the hood, this is just a nested hashmap. The outer layer represents the document sections,
-/// where the opening unnamed section is referred to with the empty string.
+/// Under the hood, this is just a nested [`Map`]. The outer layer represents the document sections,
+/// where the opening unnamed section is referred to with the empty string,
+/// or another name set with [`set_global_section`](IniDocument::set_global_section).
 /// The inner layer represents keys and values inside a section.
 /// 
-/// Currently, comments are not preserved in any way.
-#[derive(Debug, PartialEq, Default)]
+/// Comments directly above a key are kept alongside it, and written back out with it.
+/// Other comments are not preserved.
+/// 
+/// Documents can be cloned to take a snapshot before changing them, and compared with [`diff`](IniDocument::diff).
+/// Two documents are equal if they have the same sections, keys, values and comments, regardless of the order they were added in.
+#[derive(Debug, Clone, Default)]
 pub struct IniDocument {
-    sections: HashMap<String, HashMap<String, String>>
+    sections: Map<String, Section>,
+    /// extra information about keys, by section then key
+    meta: Map<String, Map<String, KeyMeta>>,
+    /// the values of append-style array keys, by section then key
+    arrays: Map<String, Map<String, Vec<String>>>,
+    /// the line each section's header was first parsed from
+    section_lines: Map<String, usize>,
+    /// the name of the section that's written without a header
+    global_section: String,
+    /// the original text, if it's being kept
+    layout: Layout,
+    #[cfg(feature = "case_insensitive")]
+    case_folding: CaseFolding,
+    #[cfg(feature = "case_insensitive")]
+    case_insensitivity: CaseInsensitivity,
+    #[cfg(feature = "case_insensitive")]
+    case_index: Option<CaseIndex>,
+    #[cfg(feature = "case_insensitive")]
+    name_case: NameCase,
 }
 impl IniDocument {
     /// Create a new empty `IniDocument`.
     pub fn empty() -> IniDocument {
-        IniDocument {
-            sections: HashMap::new()
-        }
+        IniDocument::default()
+    }
+    /// Create a new empty `IniDocument` with room for at least `sections` sections before it has to reallocate,
+    /// including the unnamed section. With the `sorted` feature, this is the same as [`empty`](IniDocument::empty).
+    pub fn with_capacity(sections: usize) -> IniDocument {
+        IniDocument { sections: map_with_capacity(sections), ..Default::default() }
     }
     /// Determine if an `IniDocument` is empty. A document that contains sections but no keys is considered empty.
     pub fn is_empty(&self) -> bool {
@@ -72,222 +164,954 @@ impl IniDocument {
             true
         }
         else {
-            !self.sections.iter().any(|(_, s)| !s.is_empty())
+            !self.sections.iter().any(|(_, s)| !s.is_empty()) && self.arrays.values().all(|a| a.is_empty())
             // get a true if any section is not empty, then not it
         }
     }
+    /// Get the total number of key/value pairs in the document, across all sections including the unnamed one.
+    /// Each value of an array key counts as a pair of its own, so this is only zero if the document [`is_empty`](IniDocument::is_empty).
+    pub fn len(&self) -> usize {
+        let arrays: usize = self.arrays.values().flat_map(|a| a.values()).map(|v| v.len()).sum();
+        self.sections.values().map(|s| s.len()).sum::<usize>() + arrays
+    }
+    /// Get the number of sections in the document, including the unnamed section if it has any keys or has been created.
+    pub fn section_count(&self) -> usize {
+        self.sections.len()
+    }
+    /// Get the number of key/value pairs in a given section, or `None` if the section doesn't exist.
+    /// Each value of an array key counts as a pair of its own, the same as in [`len`](IniDocument::len).
+    pub fn key_count<T: AsRef<str>>(&self, section: T) -> Option<usize> {
+        let section = self.lookup_section(section.as_ref());
+        let arrays: usize = self.section_arrays(section).into_iter().flat_map(|a| a.values()).map(|v| v.len()).sum();
+        self.sections.get(section).map(|s| s.len() + arrays)
+    }
     /// Insert a key into a given section. Returns the old value if it exists.
     pub fn insert<T, U, V>(&mut self, key: T, value: U, section: V) -> Option<String>
     where T: Into<String>, U: Into<String>, V: Into<String> {
-        let section: String = section.into();
-        if let Some(section) = self.sections.get_mut(&section) {
-            section.insert(key.into(), value.into())
+        let section = self.stored_section(section.into());
+        let key = self.stored_name(key.into());
+        let old = if let Some(data) = self.sections.get_mut(&section) {
+            data.0.insert(key.clone(), value.into())
         }
         else {
-            let mut h = HashMap::new();
-            h.insert(key.into(), value.into());
-            self.sections.insert(section, h);
+            let mut h = Section::default();
+            h.0.insert(key.clone(), value.into());
+            self.sections.insert(section.clone(), h);
             None
+        };
+        if old.is_none() {
+            self.index_key_inserted(&key, &section)
+        }
+        old
+    }
+    /// Insert a key into a given section, but only if it isn't already there.
+    /// If it is, nothing is changed, and the key and value are given back as the error.
+    /// 
+    /// ```
+    /// # use innit::*;
+    /// let mut document = IniDocument::empty();
+    /// assert_eq!(document.try_insert("foo", "bar", ""), Ok(()));
+    /// assert_eq!(document.try_insert("foo", "baz", ""), Err(("foo".to_string(), "baz".to_string())));
+    /// assert_eq!(document.get("foo", ""), Some("bar"));
+    /// ```
+    pub fn try_insert<T, U, V>(&mut self, key: T, value: U, section: V) -> Result<(), (String, String)>
+    where T: Into<String>, U: Into<String>, V: Into<String> {
+        let key = self.stored_name(key.into());
+        let section = self.stored_section(section.into());
+        if self.sections.get(&section).is_some_and(|s| s.contains_key(&key)) {
+            return Err((key, value.into()))
+        }
+        self.insert(key, value, section);
+        Ok(())
+    }
+    /// Insert a key into a given section, like [`insert`](IniDocument::insert), but only if the key and value can be written out
+    /// with the given [`FormatOptions`] and parsed back the same. Returns the old value if it exists.
+    /// 
+    /// Without [`FormatOptions::quoted_keys`], keys can't contain equals signs or line breaks, start or end with whitespace,
+    /// start with a comment prefix or an opening bracket, or end with `[]`, and with [`FormatOptions::quoted_values`] on,
+    /// they can't contain double quotes either. Without [`FormatOptions::quoted_values`],
+    /// values can't contain line breaks or start or end with whitespace. Section names can't contain line breaks,
+    /// but anything else is escaped, including whitespace at either end, so it isn't trimmed when they're read back.
+    /// If anything can't be written, nothing is changed, and the error says why.
+    /// 
+    /// ```
+    /// # use innit::*;
+    /// let mut document = IniDocument::empty();
+    /// let err = document.try_insert_validated("a=b", "c", "", &FormatOptions::default()).unwrap_err();
+    /// assert_eq!(err.to_string(), "can't write `a=b`, since keys can't contain an equals sign");
+    /// let options = FormatOptions { quoted_keys: true, ..Default::default() };
+    /// assert_eq!(document.try_insert_validated("a=b", "c", "", &options), Ok(None));
+    /// ```
+    pub fn try_insert_validated<T, U, V>(&mut self, key: T, value: U, section: V, options: &FormatOptions) -> Result<Option<String>, InnitError>
+    where T: Into<String>, U: Into<String>, V: Into<String> {
+        let key: String = key.into();
+        let value: String = value.into();
+        let section: String = section.into();
+        if section.contains(['\n', '\r']) {
+            return Err(InnitError::Unwritable { text: section, reason: "section names can't contain line breaks" })
+        }
+        if let Some(reason) = unwritable_key(&key, options) {
+            return Err(InnitError::Unwritable { text: key, reason })
+        }
+        if let Some(reason) = unwritable_value(&value, options) {
+            return Err(InnitError::Unwritable { text: value, reason })
+        }
+        Ok(self.insert(key, value, section))
+    }
+    /// Add `separator` and then `suffix` to the end of a key's value in a given section.
+    /// If the key doesn't exist, it's inserted with `suffix` as its value, without the separator.
+    /// 
+    /// ```
+    /// # use innit::*;
+    /// let mut document = IniDocument::empty();
+    /// document.append("PATH", "", "/bin", ":");
+    /// document.append("PATH", "", "/sbin", ":");
+    /// assert_eq!(document.get("PATH", ""), Some("/bin:/sbin"));
+    /// ```
+    pub fn append<T, U, V, W>(&mut self, key: T, section: U, suffix: V, separator: W)
+    where T: Into<String>, U: Into<String>, V: AsRef<str>, W: AsRef<str> {
+        let key = self.stored_name(key.into());
+        let section = self.stored_section(section.into());
+        match self.sections.get_mut(&section).and_then(|s| s.0.get_mut(&key)) {
+            Some(value) => {
+                value.push_str(separator.as_ref());
+                value.push_str(suffix.as_ref())
+            }
+            None => {
+                self.insert(key, suffix.as_ref(), section);
+            }
+        }
+    }
+    /// Replace a section with the key/value pairs from an iterator, creating it if it doesn't exist.
+    /// Any keys already in the section, and their comments, are removed first.
+    /// 
+    /// ```
+    /// # use innit::*;
+    /// let mut document = IniDocument::from_string("[section1]\nfoo = bar").unwrap();
+    /// document.insert_section("section1", [("baz", "bop")]);
+    /// assert_eq!(document.get("foo", "section1"), None);
+    /// assert_eq!(document.get("baz", "section1"), Some("bop"));
+    /// ```
+    pub fn insert_section<S, I, K, V>(&mut self, section: S, pairs: I)
+    where S: Into<String>, I: IntoIterator<Item = (K, V)>, K: Into<String>, V: Into<String> {
+        let section = self.stored_section(section.into());
+        let data = pairs.into_iter().map(|(k, v)| (self.stored_name(k.into()), v.into())).collect();
+        self.sections.insert(section.clone(), Section(data));
+        self.meta.remove(&section);
+        self.arrays.remove(&section);
+        self.index_section_changed(&section)
+    }
+    /// Insert the key/value pairs from an iterator into a section, creating it if it doesn't exist.
+    /// Keys already in the section are kept, unless the iterator replaces their values.
+    pub fn extend_section<S, I, K, V>(&mut self, section: S, pairs: I)
+    where S: Into<String>, I: IntoIterator<Item = (K, V)>, K: Into<String>, V: Into<String> {
+        let section = self.stored_section(section.into());
+        if !self.sections.contains_key(&section) {
+            self.sections.insert(section.clone(), Section::default());
+            self.index_section_changed(&section)
+        }
+        for (k, v) in pairs {
+            self.insert(k, v, section.as_str());
         }
     }
     /// Get a reference to a value in a given section.
     pub fn get<T: AsRef<str>>(&self, key: T, section: T) -> Option<&str> {
         let key = key.as_ref();
-        let section = section.as_ref();
+        let section = self.lookup_section(section.as_ref());
         if let Some(s) = self.sections.get(section) {
-            s.get(key).map(|s| s.as_str())
+            s.get(key)
         }
         else {
             None
         }
     }
-    /// Get an entire document section, as a hashmap.
-    pub fn get_section<T: AsRef<str>>(&self, section: T) -> Option<&HashMap<String, String>> {
-        self.sections.get(section.as_ref())
+    /// Get a value in a section named by a path, treating dots in section names as separators,
+    /// so `["database", "primary"]` is the `[database.primary]` section. An empty path is the unnamed section.
+    /// 
+    /// Sections are still stored under their full names, so this is the same as joining the path with dots and using [`get`](IniDocument::get).
+    /// 
+    /// ```
+    /// # use innit::*;
+    /// let document = IniDocument::from_string("[database.primary]\nhost = db1").unwrap();
+    /// assert_eq!(document.get_nested("host", &["database", "primary"]), Some("db1"));
+    /// ```
+    pub fn get_nested<T: AsRef<str>>(&self, key: T, path: &[T]) -> Option<&str> {
+        let section: Vec<&str> = path.iter().map(AsRef::as_ref).collect();
+        self.get(key.as_ref(), &section.join("."))
+    }
+    /// Get the text of a value exactly as it was written, before it was trimmed or unquoted, for documents parsed with
+    /// [`IniParser::preserve_formatting`] or [`IniParser::preserve_comments`].
+    /// 
+    /// This falls back to the same value as [`get`](IniDocument::get) for documents that don't keep their original text,
+    /// and for keys that were added or changed after parsing.
+    /// 
+    /// ```
+    /// # use innit::*;
+    /// let document = IniParser::new().preserve_formatting(true).quoted_values(true).parse("foo =  \"bar\"  ").unwrap();
+    /// assert_eq!(document.get("foo", ""), Some("bar"));
+    /// assert_eq!(document.get_raw("foo", ""), Some("  \"bar\"  "));
+    /// ```
+    pub fn get_raw<T: AsRef<str>>(&self, key: T, section: T) -> Option<&str> {
+        let key = key.as_ref();
+        let section = section.as_ref();
+        self.layout.raw_value(self, key, section).or_else(|| self.get(key, section))
+    }
+    /// Get a reference to a value in a given section, or `default` if it doesn't exist.
+    /// 
+    /// ```
+    /// # use innit::*;
+    /// let document = IniDocument::from_string("foo = bar").unwrap();
+    /// assert_eq!(document.get_or("foo", "", "fallback"), "bar");
+    /// assert_eq!(document.get_or("baz", "", "fallback"), "fallback");
+    /// ```
+    pub fn get_or<'a, T: AsRef<str>>(&'a self, key: T, section: T, default: &'a str) -> &'a str {
+        self.get(key, section).unwrap_or(default)
+    }
+    /// Get an owned copy of a value in a given section, or `default` if it doesn't exist.
+    pub fn get_owned_or<T: AsRef<str>, U: Into<String>>(&self, key: T, section: T, default: U) -> String {
+        match self.get(key, section) {
+            Some(v) => v.into(),
+            None => default.into()
+        }
+    }
+    /// Get a reference to a value in a given section, with any surrounding whitespace trimmed off.
+    /// 
+    /// Values are trimmed when parsed, so this is only different from [`get`](IniDocument::get) for values that were inserted with whitespace.
+    pub fn get_trimmed<T: AsRef<str>>(&self, key: T, section: T) -> Option<&str> {
+        self.get(key, section).map(str::trim)
+    }
+    /// Get a value in a given section split into a list on a separator, with whitespace trimmed off each element
+    /// and empty elements dropped, so `a, b,, c,` is `["a", "b", "c"]`.
+    /// 
+    /// This splits a single value. For keys that are given more than once, see [`get_all`](IniDocument::get_all).
+    /// 
+    /// ```
+    /// # use innit::*;
+    /// let document = IniDocument::from_string("tags = a, b ,c").unwrap();
+    /// assert_eq!(document.get_list("tags", "", ','), Some(vec!["a", "b", "c"]));
+    /// ```
+    pub fn get_list<T: AsRef<str>>(&self, key: T, section: T, sep: char) -> Option<Vec<&str>> {
+        Some(self.get(key, section)?.split(sep).map(str::trim).filter(|s| !s.is_empty()).collect())
+    }
+    /// Determine if a key exists in a given section and has exactly the expected value.
+    /// 
+    /// ```
+    /// # use innit::*;
+    /// let document = IniDocument::from_string("[app]\nmode = debug").unwrap();
+    /// assert!(document.value_is("mode", "app", "debug"));
+    /// assert!(!document.value_is("mode", "app", "release"));
+    /// ```
+    pub fn value_is<T: AsRef<str>>(&self, key: T, section: T, expected: T) -> bool {
+        self.get(key, section) == Some(expected.as_ref())
+    }
+    /// Get an entire document section.
+    pub fn get_section<T: AsRef<str>>(&self, section: T) -> Option<&Section> {
+        self.sections.get(self.lookup_section(section.as_ref()))
+    }
+    /// Iterate over the sections whose names start with a prefix, with their names, in the order they're stored in.
+    /// 
+    /// ```
+    /// # use innit::*;
+    /// let document = IniDocument::from_string("[service:web]\nport = 80\n[service:db]\nport = 5432\n[logging]\nlevel = info").unwrap();
+    /// let mut services: Vec<&str> = document.sections_with_prefix("service:").map(|(name, _)| name).collect();
+    /// services.sort();
+    /// assert_eq!(services, ["service:db", "service:web"]);
+    /// ```
+    pub fn sections_with_prefix<'a>(&'a self, prefix: &'a str) -> impl Iterator<Item = (&'a str, &'a Section)> {
+        self.sections.iter().filter(move |(name, _)| name.starts_with(prefix)).map(|(name, s)| (name.as_str(), s))
+    }
+    /// Iterate over the keys in a given section, or get `None` if the section doesn't exist.
+    pub fn keys<T: AsRef<str>>(&self, section: T) -> Option<impl Iterator<Item = &str>> {
+        self.get_section(section).map(Section::keys)
+    }
+    /// Iterate over the values in a given section, or get `None` if the section doesn't exist.
+    pub fn values<T: AsRef<str>>(&self, section: T) -> Option<impl Iterator<Item = &str>> {
+        self.get_section(section).map(Section::values)
     }
     /// Remove a key/value pair in a given section. Returns the value, if it existed.
     pub fn remove<T: AsRef<str>>(&mut self, key: T, section: T) -> Option<String> {
         let key = key.as_ref();
-        let section = section.as_ref();
-        if let Some(s) = self.sections.get_mut(section) {
-            s.remove(key)
+        let section = self.lookup_section(section.as_ref()).to_string();
+        let section = section.as_str();
+        let old = self.sections.get_mut(section)?.0.remove(key);
+        if old.is_some() {
+            self.take_meta(key, section);
+            self.index_key_removed(key, section)
         }
-        else {
-            None
+        old
+    }
+    /// Rename a key within a given section, keeping its value. Returns the value that was previously stored under the new name, if there was one.
+    /// 
+    /// If the old key doesn't exist, nothing is changed and `None` is returned.
+    pub fn rename_key<T: AsRef<str>, U: Into<String>>(&mut self, old: T, new: U, section: T) -> Option<String> {
+        let old = old.as_ref();
+        let new: String = new.into();
+        let s = self.sections.get_mut(section.as_ref())?;
+        if old == new {
+            return None
         }
+        let value = s.0.remove(old)?;
+        let displaced = s.0.insert(new.clone(), value);
+        let meta = self.take_meta(old, section.as_ref());
+        self.put_meta(&new, section.as_ref(), meta);
+        self.index_section_changed(section.as_ref());
+        displaced
+    }
+    /// Move a key and its value from one section to another, creating the destination section if needed.
+    /// Returns the value that was previously stored under the key in the destination section, if there was one.
+    /// 
+    /// If the key doesn't exist in the source section, nothing is changed and `None` is returned.
+    pub fn move_key<T: AsRef<str>>(&mut self, key: T, from_section: T, to_section: T) -> Option<String> {
+        let key = key.as_ref();
+        let meta = self.meta.get(from_section.as_ref()).and_then(|m| m.get(key)).cloned();
+        let value = self.remove(key, from_section.as_ref())?;
+        let displaced = self.insert(key, value, to_section.as_ref());
+        self.put_meta(key, to_section.as_ref(), meta);
+        displaced
+    }
+    /// Get the comment attached to a key, from the lines directly above it.
+    /// The comment characters are removed, and comments over several lines are joined with newlines.
+    pub fn get_comment<T: AsRef<str>>(&self, key: T, section: T) -> Option<&str> {
+        self.meta.get(section.as_ref())?.get(key.as_ref())?.comment.as_deref()
+    }
+    /// Attach a comment to a key, to be written on the lines above it. Newlines in the comment split it over several lines.
+    /// Returns the old comment, if there was one.
+    /// 
+    /// Comments can only be attached to keys that exist, so nothing happens if the key doesn't exist.
+    pub fn set_comment<T: AsRef<str>, U: Into<String>>(&mut self, key: T, section: T, comment: U) -> Option<String> {
+        let key = key.as_ref();
+        let section = section.as_ref();
+        self.sections.get(section)?.get(key)?;
+        let meta = self.meta.entry(section.into()).or_default().entry(key.into()).or_default();
+        meta.comment.replace(comment.into())
+    }
+    /// Remove the comment attached to a key. Returns the comment, if there was one.
+    pub fn remove_comment<T: AsRef<str>>(&mut self, key: T, section: T) -> Option<String> {
+        self.meta.get_mut(section.as_ref())?.get_mut(key.as_ref())?.comment.take()
+    }
+    /// Get the line a key was parsed from, counting from 1. If the key was set more than once, this is the line of the one that was kept.
+    /// Keys that were inserted rather than parsed don't have a line, but keys that were parsed and then changed keep theirs.
+    /// 
+    /// ```
+    /// # use innit::*;
+    /// let document = IniDocument::from_string("foo = bar\n[section1]\nfoo = baz\nfoo = bop").unwrap();
+    /// assert_eq!(document.key_line("foo", ""), Some(1));
+    /// assert_eq!(document.key_line("foo", "section1"), Some(4));
+    /// assert_eq!(document.section_line("section1"), Some(2));
+    /// ```
+    pub fn key_line<T: AsRef<str>>(&self, key: T, section: T) -> Option<usize> {
+        self.meta.get(section.as_ref())?.get(key.as_ref())?.line
+    }
+    /// Get the line a section's header was parsed from, counting from 1. If the section had more than one header, this is the first.
+    /// The unnamed section doesn't have a header, so it doesn't have a line.
+    pub fn section_line<T: AsRef<str>>(&self, section: T) -> Option<usize> {
+        // headers on their own don't make a section
+        self.section_lines.get(section.as_ref()).copied().filter(|_| self.sections.contains_key(section.as_ref()))
     }
     /// Remove an entire section. Returns the section, if it existed.
-    pub fn remove_section<T: AsRef<str>>(&mut self, section: T) -> Option<HashMap<String, String>> {
+    /// 
+    /// The empty string removes the unnamed section, which takes every key before the first section header with it.
+    /// [`clear_global`](IniDocument::clear_global) does the same thing, but makes it clearer that that's what's intended.
+    pub fn remove_section<T: AsRef<str>>(&mut self, section: T) -> Option<Section> {
         let section = section.as_ref();
-        self.sections.remove(section)
+        let old = self.sections.remove(section);
+        self.meta.remove(section);
+        self.arrays.remove(section);
+        self.section_lines.remove(section);
+        self.index_section_changed(section);
+        old
     }
-
-    /// Parse a document from a string. Comments are not preserved when writing back to a string, so watch out!
+    /// Remove every key from the unnamed section, along with their comments and any array keys, leaving the named sections as they are.
+    /// Returns the keys and values that were removed, which is empty if there weren't any.
     /// 
-    /// Inline comments are not supported.
-    pub fn from_string<T: AsRef<str>>(s: T) -> Result<IniDocument, InnitError> {
-        let s = s.as_ref();
-        let mut document = IniDocument::empty();
-        let mut cur_section = "";
-        for (lnum, line) in s.split(LINE_DELIM).enumerate() {
-            let line = line.trim();
-            if !string_is_comment_or_empty(line) { // ignore comments outright
-                if let Some(name) = string_is_section_start(line) {
-                    if name == "" {
-                        return Err(InnitError::EmptyStringSection(lnum + 1))
-                    }
-                    cur_section = name
-                }
-                else {
-                    let (k, v) = parse_k_v(line).ok_or_else(|| InnitError::MissingEquals(line.into(), lnum + 1))?;
-                    document.insert(k, v, cur_section);
+    /// ```
+    /// # use innit::*;
+    /// let mut document = IniDocument::from_string("foo = bar\n[section1]\nfoo = baz").unwrap();
+    /// assert_eq!(document.clear_global().get("foo"), Some("bar"));
+    /// assert_eq!(document.get("foo", ""), None);
+    /// assert_eq!(document.get("foo", "section1"), Some("baz"));
+    /// ```
+    pub fn clear_global(&mut self) -> Section {
+        let name = self.global_section.clone();
+        self.remove_section(name).unwrap_or_default()
+    }
+    /// Get the name of the section that's written without a header, which is the empty string unless it's been changed.
+    pub fn global_section(&self) -> &str {
+        &self.global_section
+    }
+    /// Set the name of the section that's written without a header, like [`IniParser::global_section`] does when parsing.
+    /// The section that had been global until now is written with a header, unless it's the empty string.
+    /// 
+    /// The empty string always stands for the global section when inserting, getting and removing keys,
+    /// so keys already stored under it are moved into the new global section, replacing any with the same name.
+    /// 
+    /// ```
+    /// # use innit::*;
+    /// let mut document = IniDocument::from_string("foo = bar\n[DEFAULT]\nbaz = bop").unwrap();
+    /// document.set_global_section("DEFAULT");
+    /// assert_eq!(document.get("foo", "DEFAULT"), Some("bar"));
+    /// assert_eq!(document.get("baz", ""), Some("bop"));
+    /// assert!(!document.to_string().contains("["));
+    /// ```
+    pub fn set_global_section<T: Into<String>>(&mut self, name: T) {
+        let name = self.stored_name(name.into());
+        // while the empty string is the global section, it doesn't stand for anything else
+        self.global_section.clear();
+        if name != "" && self.sections.contains_key("") {
+            let keys: Vec<String> = self.sections[""].keys().map(String::from).collect();
+            for key in keys {
+                self.move_key(key.as_str(), "", name.as_str());
+            }
+            for (key, values) in self.arrays.remove("").unwrap_or_default() {
+                for value in values {
+                    self.push(key.as_str(), value, name.as_str())
                 }
             }
+            self.remove_section("");
+        }
+        self.global_section = name
+    }
+    /// Remove every section, key and value from the document.
+    pub fn clear(&mut self) {
+        self.meta.clear();
+        self.arrays.clear();
+        self.section_lines.clear();
+        self.layout.forget_lines();
+        let names: Vec<String> = std::mem::take(&mut self.sections).into_keys().collect();
+        for name in names {
+            self.index_section_changed(&name)
+        }
+    }
+    /// Remove every key and value from a section, but keep the section itself, so it's still written out as an empty section.
+    pub fn clear_section<T: AsRef<str>>(&mut self, section: T) {
+        if let Some(s) = self.sections.get_mut(section.as_ref()) {
+            s.0.clear();
+            self.meta.remove(section.as_ref());
+            self.arrays.remove(section.as_ref());
+            self.index_section_changed(section.as_ref())
+        }
+    }
+    /// Keep only the key/value pairs in a given section that the closure returns true for, removing the rest along with their comments.
+    /// 
+    /// The section is kept even if every key is removed. Use [`remove_empty_sections`](IniDocument::remove_empty_sections) to get rid of it.
+    pub fn retain_keys<T: AsRef<str>, F: FnMut(&str, &str) -> bool>(&mut self, section: T, mut f: F) {
+        let section = section.as_ref();
+        let s = match self.sections.get_mut(section) {
+            Some(s) => s,
+            None => return
+        };
+        let mut removed = Vec::new();
+        s.0.retain(|k, v| {
+            let keep = f(k, v);
+            if !keep {
+                removed.push(k.clone())
+            }
+            keep
+        });
+        for key in removed {
+            self.take_meta(&key, section);
+            self.index_key_removed(&key, section)
+        }
+    }
+    /// Keep only the sections that the closure returns true for, removing the rest along with all their keys.
+    pub fn retain_sections<F: FnMut(&str, &Section) -> bool>(&mut self, mut f: F) {
+        let removed: Vec<String> = self.sections.iter().filter(|(name, s)| !f(name, s)).map(|(name, _)| name.clone()).collect();
+        for name in removed {
+            self.remove_section(name);
+        }
+    }
+    /// Remove every section that has no keys, including array keys.
+    pub fn remove_empty_sections(&mut self) {
+        let removed: Vec<String> = self.sections.iter()
+            .filter(|(name, s)| s.is_empty() && self.section_arrays(name).is_none())
+            .map(|(name, _)| name.clone())
+            .collect();
+        for name in removed {
+            self.remove_section(name);
         }
+    }
+    /// Rename a section, keeping all of its keys and values.
+    /// 
+    /// Fails if `to` already exists or is the empty string, or if `from` doesn't exist.
+    /// Renaming a section to its own name does nothing.
+    /// The unnamed section can be renamed, which moves all of its keys into a new named section.
+    pub fn rename_section<T: AsRef<str>, U: Into<String>>(&mut self, from: T, to: U) -> Result<(), InnitError> {
+        let from = from.as_ref();
+        let to: String = to.into();
+        if !self.sections.contains_key(from) {
+            return Err(InnitError::NoSuchSection(from.into()))
+        }
+        if from == to {
+            return Ok(())
+        }
+        if to == "" {
+            return Err(InnitError::EmptySectionName)
+        }
+        if self.sections.contains_key(&to) {
+            return Err(InnitError::SectionExists(to))
+        }
+        let data = self.sections.remove(from).unwrap();
+        self.sections.insert(to.clone(), data);
+        if let Some(meta) = self.meta.remove(from) {
+            self.meta.insert(to.clone(), meta);
+        }
+        if let Some(arrays) = self.arrays.remove(from) {
+            self.arrays.insert(to.clone(), arrays);
+        }
+        if let Some(line) = self.section_lines.remove(from) {
+            self.section_lines.insert(to.clone(), line);
+        }
+        self.index_section_changed(from);
+        self.index_section_changed(&to);
+        Ok(())
+    }
 
-        Ok(document)
+    /// Parse a document from a string. Only comments directly above a key are preserved when writing back to a string, so watch out!
+    /// 
+    /// Inline comments are not supported.
+    pub fn from_string<T: AsRef<str>>(s: T) -> Result<IniDocument, InnitError> {
+        IniParser::new().parse(s)
+    }
+    /// Parse a document from a string, like [`from_string`](IniDocument::from_string),
+    /// and also return a warning for each key that replaced an earlier value. See [`IniParser::parse_with_warnings`].
+    pub fn from_string_with_warnings<T: AsRef<str>>(s: T) -> Result<(IniDocument, Vec<Warning>), InnitError> {
+        IniParser::new().parse_with_warnings(s)
+    }
+    /// Parse a string and layer it onto this document, for loading a base file and then applying overrides on top of it.
+    /// Keys in the string replace any that are already there, along with their comments, and new sections are added.
+    /// 
+    /// The whole string is parsed before anything is changed, so if it fails to parse, the document is left exactly as it was.
+    /// Use [`IniParser::parse_onto`] to parse with other options.
+    /// 
+    /// ```
+    /// # use innit::*;
+    /// let mut document = IniDocument::from_string("[server]\nhost = localhost\nport = 80").unwrap();
+    /// document.apply_string("[server]\nport = 8080").unwrap();
+    /// assert_eq!(document.get("host", "server"), Some("localhost"));
+    /// assert_eq!(document.get("port", "server"), Some("8080"));
+    /// ```
+    pub fn apply_string<T: AsRef<str>>(&mut self, s: T) -> Result<(), InnitError> {
+        IniParser::new().parse_onto(self, s)
     }
-    /// Turn a document back into its string representation. Ordering of sections, keys and values is not preserved, due to limitations of Rust's hashmap struct.
-    pub fn to_string(&self) -> String {
-        let mut ret = String::new();
+    /// Parse a single line into the document, for building a document up one line at a time.
+    /// 
+    /// `current_section` is the section that key/value pairs are inserted into,
+    /// and is updated when the line is a section header. Start it off as the empty string for the unnamed section.
+    /// Since there's no surrounding document, any errors report the line as line 1.
+    /// 
+    /// ```
+    /// # use innit::*;
+    /// let mut document = IniDocument::empty();
+    /// let mut section = String::new();
+    /// document.parse_line("foo = bar", &mut section).unwrap();
+    /// document.parse_line("[section1]", &mut section).unwrap();
+    /// document.parse_line("foo = baz", &mut section).unwrap();
+    /// assert_eq!(section, "section1");
+    /// assert_eq!(document.get("foo", "section1"), Some("baz"));
+    /// ```
+    pub fn parse_line(&mut self, line: &str, current_section: &mut String) -> Result<(), InnitError> {
+        IniParser::new().parse_line(self, line, current_section)
+    }
+}
 
-        if let Some(start) = self.sections.get("") {
-            ret.push_str(&fmt_hashmap(start))
+/// Build a document from a map of sections, where the unnamed section is stored under the empty string, just like [`insert`](IniDocument::insert).
+/// Empty sections are kept, and are written out as empty sections.
+impl From<HashMap<String, HashMap<String, String>>> for IniDocument {
+    fn from(sections: HashMap<String, HashMap<String, String>>) -> IniDocument {
+        let mut document = IniDocument::empty();
+        for (name, data) in sections {
+            document.insert_section(name, data)
         }
+        document
+    }
+}
 
-        for (k, v) in &self.sections {
-            if k == "" {
-                continue
-            }
-            ret.push_str(&format!("[{}]{}", k, LINE_DELIM));
-            ret.push_str(&fmt_hashmap(v))
+/// Build a document from `(key, value, section)` tuples, inserting them in order with [`insert`](IniDocument::insert),
+/// so a later value for the same key replaces an earlier one.
+/// 
+/// ```
+/// # use innit::*;
+/// let document: IniDocument = [("foo", "bar", ""), ("foo", "baz", "section1")].into_iter().collect();
+/// assert_eq!(document.get("foo", ""), Some("bar"));
+/// assert_eq!(document.get("foo", "section1"), Some("baz"));
+/// ```
+impl<K, V, S> FromIterator<(K, V, S)> for IniDocument
+where K: Into<String>, V: Into<String>, S: Into<String> {
+    fn from_iter<I: IntoIterator<Item = (K, V, S)>>(iter: I) -> IniDocument {
+        let mut document = IniDocument::empty();
+        for (k, v, s) in iter {
+            document.insert(k, v, s);
         }
-
-        ret
+        document
     }
 }
 
-#[cfg(feature = "case_insensitive")]
 impl IniDocument {
-    /// Get a reference to a value in a given section, using case-insensitive matching.
-    pub fn get_case_insensitive<T: AsRef<str>>(&self, key: T, section: T) -> Option<&str> {
-        let section = section.as_ref().to_lowercase();
-        for (name, data) in &self.sections {
-            if name.to_lowercase() == section {
-                let key = key.as_ref().to_lowercase();
-                for (k, v) in data {
-                    if k.to_lowercase() == key {
-                        return Some(v)
-                    }
-                }
-            }
-        }
-        None
+    /// Iterate over every key/value pair in the document as `(key, value, section)` tuples, in the order they're stored in.
+    /// Array keys aren't included, see [`get_all`](IniDocument::get_all) for those.
+    /// 
+    /// ```
+    /// # use innit::*;
+    /// let document = IniDocument::from_string("foo = bar\n[section1]\nfoo = baz").unwrap();
+    /// let mut pairs: Vec<(&str, &str, &str)> = document.iter().collect();
+    /// pairs.sort();
+    /// assert_eq!(pairs, [("foo", "bar", ""), ("foo", "baz", "section1")]);
+    /// ```
+    pub fn iter(&self) -> Iter<'_> {
+        self.into_iter()
     }
-    /// Get a section, using case-insensitive matching.
-    pub fn get_section_case_insensitive<T: AsRef<str>>(&self,section: T) -> Option<&HashMap<String, String>> {
-        let section = section.as_ref().to_lowercase();
-        for (name, data) in &self.sections {
-            if name.to_lowercase() == section {
-                return Some(data)
+    /// Find every key/value pair in the document that the predicate returns true for, as `(section, key, value)` tuples.
+    /// The predicate is called with the section, key and value, in that order. Array keys aren't included.
+    /// 
+    /// ```
+    /// # use innit::*;
+    /// let document = IniDocument::from_string("[db]\npassword = hunter2\n[cache]\npassword = swordfish\nhost = localhost").unwrap();
+    /// let mut passwords = document.find(|_, key, _| key == "password");
+    /// passwords.sort();
+    /// assert_eq!(passwords, [("cache", "password", "swordfish"), ("db", "password", "hunter2")]);
+    /// ```
+    pub fn find(&self, pred: impl Fn(&str, &str, &str) -> bool) -> Vec<(&str, &str, &str)> {
+        self.iter().filter(|(k, v, s)| pred(s, k, v)).map(|(k, v, s)| (s, k, v)).collect()
+    }
+    /// Replace every value in the document with what the closure returns for it, leaving sections and keys as they are.
+    /// The closure is called with the section, key and value, in that order, like [`find`](IniDocument::find).
+    /// Each value of an array key is passed in on its own, with the name of the key.
+    /// 
+    /// ```
+    /// # use innit::*;
+    /// let mut document = IniDocument::from_string("[db]\nuser = admin\npassword = hunter2").unwrap();
+    /// document.map_values(|_, key, value| if key == "password" { "***".into() } else { value.into() });
+    /// assert_eq!(document.get("password", "db"), Some("***"));
+    /// assert_eq!(document.get("user", "db"), Some("admin"));
+    /// ```
+    pub fn map_values(&mut self, mut f: impl FnMut(&str, &str, &str) -> String) {
+        for (section, data) in self.sections.iter_mut() {
+            for (key, value) in data.0.iter_mut() {
+                *value = f(section, key, value)
             }
         }
-        None
-    }
-
-    /// Remove a key/value pair in a given section, using case-insensitive matching. Returns the value, if it existed.
-    pub fn remove_case_insensitive<T: AsRef<str>>(&mut self, key: T, section: T) -> Option<String> {
-        let section = section.as_ref().to_lowercase();
-        let mut exists = false;
-        let mut actual_section = String::new(); // store these back outside to appease the borrow checker
-        let mut actual_key = String::new();
-
-        'outer: for (name, data) in self.sections.iter_mut() {
-            if name.to_lowercase() == section {
-                actual_section = name.to_string();
-                let key = key.as_ref().to_lowercase();
-                for (k, _) in data {
-                    if k.to_lowercase() == key {
-                        actual_key = k.to_string();
-                        exists = true;
-                        break 'outer
-                    }
+        for (section, arrays) in self.arrays.iter_mut() {
+            for (key, values) in arrays.iter_mut() {
+                for value in values.iter_mut() {
+                    *value = f(section, key, value)
                 }
             }
         }
-        if exists {
-            self.sections.get_mut(&actual_section).unwrap().remove(&actual_key)
-        }
-        else {
-            None
-        }
     }
-    /// Remove a section, using case-insensitive matching. Returns the section, if it existed.
-    pub fn remove_section_case_insensitive<T: AsRef<str>>(&mut self, section: T) -> Option<HashMap<String, String>> {
-        let section = section.as_ref().to_lowercase();
-        let mut exists = false;
-        let mut actual_section = String::new(); // store this back outside to appease the borrow checker
+}
 
-        for (name, _) in self.sections.iter_mut() {
-            if name.to_lowercase() == section {
-                actual_section = name.to_string();
-                exists = true;
-                break
+/// An iterator over the `(key, value, section)` tuples of a document, as returned by [`IniDocument::iter`].
+#[derive(Debug)]
+pub struct Iter<'a> {
+    sections: <&'a Map<String, Section> as IntoIterator>::IntoIter,
+    current: Option<(&'a str, <&'a SectionMap as IntoIterator>::IntoIter)>,
+}
+impl<'a> Iterator for Iter<'a> {
+    type Item = (&'a str, &'a str, &'a str);
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            if let Some((section, pairs)) = &mut self.current {
+                if let Some((k, v)) = pairs.next() {
+                    return Some((k, v, section))
+                }
             }
-        }
-        if exists {
-            self.sections.remove(&actual_section)
-        }
-        else {
-            None
+            let (section, data) = self.sections.next()?;
+            self.current = Some((section, data.0.iter()))
         }
     }
 }
+/// Iterate over every key/value pair in the document as `(key, value, section)` tuples, the same as [`iter`](IniDocument::iter).
+impl<'a> IntoIterator for &'a IniDocument {
+    type Item = (&'a str, &'a str, &'a str);
+    type IntoIter = Iter<'a>;
+    fn into_iter(self) -> Iter<'a> {
+        Iter { sections: self.sections.iter(), current: None }
+    }
+}
 
-/// format a hashmap
-fn fmt_hashmap(h: &HashMap<String, String>) -> String {
-    let mut ret = String::new();
+/// An iterator that moves the `(key, value, section)` tuples out of a document.
+#[derive(Debug)]
+pub struct IntoIter {
+    sections: <Map<String, Section> as IntoIterator>::IntoIter,
+    current: Option<(String, <SectionMap as IntoIterator>::IntoIter)>,
+}
+impl Iterator for IntoIter {
+    type Item = (String, String, String);
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            if let Some((section, pairs)) = &mut self.current {
+                if let Some((k, v)) = pairs.next() {
+                    return Some((k, v, section.clone()))
+                }
+            }
+            let (section, data) = self.sections.next()?;
+            self.current = Some((section, data.0.into_iter()))
+        }
+    }
+}
+/// Turn the document into `(key, value, section)` tuples, the same form [`FromIterator`] takes, in the order they're stored in.
+/// Comments and array keys are dropped.
+impl IntoIterator for IniDocument {
+    type Item = (String, String, String);
+    type IntoIter = IntoIter;
+    fn into_iter(self) -> IntoIter {
+        IntoIter { sections: self.sections.into_iter(), current: None }
+    }
+}
 
-    for (k, v) in h {
-        ret.push_str(&format!("{} = {}{}", k, v, LINE_DELIM))
+/// Equality only depends on what's in the documents, so it's the same whatever order things are stored in.
+/// Settings that don't change the contents, like the original formatting kept by [`IniParser::preserve_formatting`], are ignored.
+impl PartialEq for IniDocument {
+    fn eq(&self, other: &IniDocument) -> bool {
+        self.sections == other.sections && self.comments() == other.comments() && self.non_empty_arrays() == other.non_empty_arrays()
     }
+}
 
-    ret
+impl IniDocument {
+    /// every comment in the document, by section and key
+    fn comments(&self) -> Map<(&str, &str), &str> {
+        self.meta.iter()
+            .flat_map(|(s, keys)| keys.iter().filter_map(move |(k, m)| Some(((s.as_str(), k.as_str()), m.comment.as_deref()?))))
+            .collect()
+    }
+    /// every array key that has any values, by section
+    fn non_empty_arrays(&self) -> Map<&str, &Map<String, Vec<String>>> {
+        self.sections.keys().filter_map(|s| Some((s.as_str(), self.section_arrays(s)?))).collect()
+    }
+    /// remove and return the metadata for a key
+    fn take_meta(&mut self, key: &str, section: &str) -> Option<KeyMeta> {
+        self.meta.get_mut(section)?.remove(key)
+    }
+    /// remember where a key was parsed from
+    pub(crate) fn set_key_line(&mut self, key: &str, section: &str, line: usize) {
+        self.meta.entry(section.into()).or_default().entry(key.into()).or_default().line = Some(line)
+    }
+    /// remember where a section's header was parsed from, unless it's already had one
+    pub(crate) fn set_section_line(&mut self, section: &str, line: usize) {
+        if !self.section_lines.contains_key(section) {
+            self.section_lines.insert(section.into(), line);
+        }
+    }
+    /// attach metadata to a key, replacing what was there
+    fn put_meta(&mut self, key: &str, section: &str, meta: Option<KeyMeta>) {
+        match meta {
+            Some(meta) => {
+                self.meta.entry(section.into()).or_default().insert(key.into(), meta);
+            }
+            None => {
+                self.take_meta(key, section);
+            }
+        }
+    }
 }
 
-#[cfg(feature = "crlf")]
-const LINE_DELIM: &str = "\r\n";
-#[cfg(not(feature = "crlf"))]
-const LINE_DELIM: &str = "\n";
+/// Information about a key that isn't part of its value.
+#[derive(Debug, Default, Clone, PartialEq)]
+struct KeyMeta {
+    comment: Option<String>,
+    /// the line it was parsed from, if it was
+    line: Option<usize>,
+}
 
-fn string_is_comment_or_empty(s: &str) -> bool {
-    s.is_empty()|| s.starts_with('#') || s.starts_with(';')
+#[cfg(not(feature = "case_insensitive"))]
+impl IniDocument {
+    // there's no case-insensitive index without the feature, so nothing to keep in sync
+    fn index_key_inserted(&mut self, _key: &str, _section: &str) {}
+    fn index_key_removed(&mut self, _key: &str, _section: &str) {}
+    fn index_section_changed(&mut self, _section: &str) {}
+    fn stored_name(&self, name: String) -> String {
+        name
+    }
 }
-/// returns Some if it is
-fn string_is_section_start(s: &str) -> Option<&str> {
-    if s.starts_with('[') && s.ends_with(']') {
-        Some(&s[1..s.len() - 1])
+
+impl IniDocument {
+    /// the name a section is stored under, with the empty string standing for the global section
+    pub(crate) fn stored_section(&self, name: String) -> String {
+        if name == "" {
+            self.global_section.clone()
+        }
+        else {
+            self.stored_name(name)
+        }
     }
-    else {
-        None
+    /// the name to look a section up by, with the empty string standing for the global section
+    pub(crate) fn lookup_section<'a>(&'a self, name: &'a str) -> &'a str {
+        if name == "" {
+            &self.global_section
+        }
+        else {
+            name
+        }
     }
 }
-fn parse_k_v(s: &str) -> Option<(&str, &str)> {
-    let split = s.split_once('=')?;
-    Some((split.0.trim(), split.1.trim()))
-}
 
-/// The error returned from the document parse method.
+/// the default line ending, for checking output in tests
+#[cfg(test)]
+const LINE_DELIM: &str = LineEnding::DEFAULT.as_str();
+
+/// The error returned from the document parse method, and from document operations that can fail.
 /// 
 /// The numbers inside the variants are the line numbers on which the error occured.
+/// Parse errors also carry the text of the offending line, so that their messages make sense on their own,
+/// and the byte range of the line within the input, not including the line ending, which can be got with [`span`](InnitError::span).
 #[derive(Debug, Error, PartialEq)]
 pub enum InnitError {
     /// A line inside a section was missing an equals sign, and is therefore an invalid key/value pair.
     #[error("bad k/v pair `{0}` on line {1}")]
-    MissingEquals(String, usize),
+    MissingEquals(String, usize, Range<usize>),
     /// A section was defined with the empty string as the name.
-    #[error("section with empty string as name on line {0}")]
-    EmptyStringSection(usize)
+    #[error("section with empty string as name `{0}` on line {1}")]
+    EmptyStringSection(String, usize, Range<usize>),
+    /// A key/value pair appeared before the first section header, when the parser requires every key to be in a section.
+    #[error("key `{key}` outside of any section in `{text}` on line {line}")]
+    KeyOutsideSection {
+        /// The key that was found.
+        key: String,
+        /// The text of the line it was found on.
+        text: String,
+        /// The line it was found on.
+        line: usize,
+        /// The byte range of the line within the input.
+        span: Range<usize>,
+    },
+    /// A line looked like a section header, but was missing its opening or closing bracket.
+    #[error("malformed section header `{text}` on line {line}")]
+    MalformedSection {
+        /// The text of the line.
+        text: String,
+        /// The line it was found on.
+        line: usize,
+        /// The byte range of the line within the input.
+        span: Range<usize>,
+    },
+    /// A key had an empty value, when the parser is set to [`EmptyValuePolicy::Error`].
+    #[error("key `{key}` has an empty value on line {line}")]
+    EmptyValue {
+        /// The key.
+        key: String,
+        /// The line it was found on.
+        line: usize,
+        /// The byte range of the line within the input.
+        span: Range<usize>,
+    },
+    /// A section had a header more than once, when the parser is set to [`DuplicateSectionPolicy::Error`].
+    #[error("duplicate section `{name}` on line {line}")]
+    DuplicateSection {
+        /// The name of the section.
+        name: String,
+        /// The line of the later header.
+        line: usize,
+        /// The byte range of the line within the input.
+        span: Range<usize>,
+    },
+    /// A section header went over the limit set with [`IniParser::max_sections`].
+    #[error("more than {limit} sections, on line {line}")]
+    TooManySections {
+        /// The most sections allowed.
+        limit: usize,
+        /// The line of the header that went over the limit.
+        line: usize,
+        /// The byte range of the line within the input.
+        span: Range<usize>,
+    },
+    /// A key went over the limit set with [`IniParser::max_keys_per_section`].
+    #[error("more than {limit} keys in section `{section}`, on line {line}")]
+    TooManyKeys {
+        /// The section the key is in.
+        section: String,
+        /// The most keys allowed in each section.
+        limit: usize,
+        /// The line of the key that went over the limit.
+        line: usize,
+        /// The byte range of the line within the input.
+        span: Range<usize>,
+    },
+    /// A line was longer than the limit set with [`IniParser::max_line_length`].
+    #[error("line {line} is longer than {limit} bytes")]
+    LineTooLong {
+        /// The most bytes allowed in a line.
+        limit: usize,
+        /// The line that was too long.
+        line: usize,
+        /// The byte range of the line within the input.
+        span: Range<usize>,
+    },
+    /// A key, value or section header had a control character in it, when the parser is set to
+    /// [reject them](IniParser::reject_control_characters).
+    #[error("control character {ch:?} on line {line}")]
+    InvalidCharacter {
+        /// The character that was found.
+        ch: char,
+        /// The line it was found on.
+        line: usize,
+        /// The byte range of the line within the input.
+        span: Range<usize>,
+    },
+    /// A key or value couldn't be written out and parsed back the same. See [`IniDocument::try_insert_validated`].
+    #[error("can't write `{text}`, since {reason}")]
+    Unwritable {
+        /// The key or value.
+        text: String,
+        /// Why it can't be written.
+        reason: &'static str,
+    },
+    /// A value had a line break in it, which can't be written out without quoting. See [`IniDocument::try_to_string_with`].
+    #[error("value of `{key}` in section `{section}` has a line break in it, which can only be written with quoted values")]
+    LineBreakInValue {
+        /// The key.
+        key: String,
+        /// The section the key is in.
+        section: String,
+    },
+    /// A JSON value couldn't be turned into a document, with the path to it and a description of what was found there.
+    /// See [`IniDocument::from_json_value`].
+    #[cfg(feature = "json")]
+    #[error("JSON value at `{path}` is {found}, which can't be stored in an INI document")]
+    UnsupportedJson {
+        /// Where the value is, like `section.key`, or the empty string for the whole value.
+        path: String,
+        /// What was found there.
+        found: &'static str,
+    },
+    /// An operation tried to give a section the empty string as its name.
+    #[error("sections cannot be given the empty string as a name")]
+    EmptySectionName,
+    /// An operation tried to create a section that already exists.
+    #[error("section `{0}` already exists")]
+    SectionExists(String),
+    /// An operation needed a section that doesn't exist.
+    #[error("section `{0}` does not exist")]
+    NoSuchSection(String),
+}
+impl InnitError {
+    /// Get the line number the error occured on, if it came from parsing.
+    pub fn line(&self) -> Option<usize> {
+        match self {
+            InnitError::MissingEquals(_, line, _) | InnitError::EmptyStringSection(_, line, _) | InnitError::KeyOutsideSection { line, .. }
+            | InnitError::MalformedSection { line, .. } | InnitError::DuplicateSection { line, .. } | InnitError::TooManySections { line, .. }
+            | InnitError::TooManyKeys { line, .. } | InnitError::LineTooLong { line, .. } | InnitError::InvalidCharacter { line, .. }
+            | InnitError::EmptyValue { line, .. } => Some(*line),
+            _ => None
+        }
+    }
+    /// Get the byte range of the offending line within the input, if the error came from parsing.
+    /// The range doesn't include the line ending, so slicing the input with it gives exactly the line as written.
+    /// 
+    /// ```
+    /// # use innit::*;
+    /// let ini = "foo = bar\nbeans\nbaz = bop";
+    /// let err = IniDocument::from_string(ini).unwrap_err();
+    /// assert_eq!(&ini[err.span().unwrap()], "beans");
+    /// ```
+    pub fn span(&self) -> Option<Range<usize>> {
+        match self {
+            InnitError::MissingEquals(_, _, span) | InnitError::EmptyStringSection(_, _, span) | InnitError::KeyOutsideSection { span, .. }
+            | InnitError::MalformedSection { span, .. } | InnitError::DuplicateSection { span, .. } | InnitError::TooManySections { span, .. }
+            | InnitError::TooManyKeys { span, .. } | InnitError::LineTooLong { span, .. } | InnitError::InvalidCharacter { span, .. }
+            | InnitError::EmptyValue { span, .. } => Some(span.clone()),
+            _ => None
+        }
+    }
 }
 
 #[cfg(test)]
@@ -319,24 +1143,535 @@ foo = baz";
     fn errors() {
         let ini = "beans";
         let document = IniDocument::from_string(ini);
-        assert_eq!(document, Err(InnitError::MissingEquals("beans".into(), 1)))
+        assert_eq!(document, Err(InnitError::MissingEquals("beans".into(), 1, 0..5)))
+    }
+
+    #[test]
+    fn parse_line() {
+        let mut document = IniDocument::empty();
+        let mut section = String::new();
+        for line in ["foo = bar", "# comment", "", "[section1]", "foo = baz"] {
+            document.parse_line(line, &mut section).unwrap();
+        }
+        assert_eq!(section, "section1");
+        assert_eq!(document.get("foo", ""), Some("bar"));
+        assert_eq!(document.get("foo", "section1"), Some("baz"));
+
+        assert_eq!(document.parse_line("beans", &mut section), Err(InnitError::MissingEquals("beans".into(), 1, 0..5)));
+        assert_eq!(document.parse_line("[]", &mut section), Err(InnitError::EmptyStringSection("[]".into(), 1, 0..2)));
+        assert_eq!(section, "section1");
+    }
+
+    #[test]
+    fn move_key() {
+        let ini = r"foo = bar
+baz = bop
+[section1]
+baz = qux";
+        let mut document = IniDocument::from_string(ini).unwrap();
+
+        assert_eq!(document.move_key("foo", "", "new"), None);
+        assert_eq!(document.get("foo", ""), None);
+        assert_eq!(document.get("foo", "new"), Some("bar"));
+
+        assert_eq!(document.move_key("baz", "", "section1"), Some("qux".into()));
+        assert_eq!(document.get("baz", ""), None);
+        assert_eq!(document.get("baz", "section1"), Some("bop"));
+
+        assert_eq!(document.move_key("missing", "", "section1"), None);
+        assert_eq!(document.get_section("section1").unwrap().len(), 1);
+    }
+
+    #[test]
+    fn try_insert() {
+        let mut document = IniDocument::empty();
+        assert_eq!(document.try_insert("foo", "bar", "section1"), Ok(()));
+        assert_eq!(document.try_insert("foo", "baz", "section1"), Err(("foo".into(), "baz".into())));
+        assert_eq!(document.get("foo", "section1"), Some("bar"));
+        assert_eq!(document.try_insert("foo", "baz", "section2"), Ok(()));
+        assert_eq!(document.len(), 2);
+    }
+
+    #[test]
+    fn try_insert_validated() {
+        let plain = FormatOptions::default();
+        let mut document = IniDocument::empty();
+        let err = document.try_insert_validated("a=b", "c", "section1", &plain).unwrap_err();
+        assert_eq!(err, InnitError::Unwritable { text: "a=b".into(), reason: "keys can't contain an equals sign" });
+        for key in ["two\nlines", " padded", "# comment", "; comment", "[header]"] {
+            assert!(document.try_insert_validated(key, "c", "section1", &plain).is_err(), "{:?}", key);
+        }
+        for value in ["two\nlines", "padded "] {
+            assert!(document.try_insert_validated("key", value, "section1", &plain).is_err(), "{:?}", value);
+        }
+        let err = document.try_insert_validated("k[]", "c", "section1", &plain).unwrap_err();
+        assert_eq!(err, InnitError::Unwritable { text: "k[]".into(), reason: "keys can't end with `[]`, since they'd be read as array keys" });
+        let quoted_values = FormatOptions { quoted_values: true, ..Default::default() };
+        let err = document.try_insert_validated("a\"b", "c", "section1", &quoted_values).unwrap_err();
+        assert_eq!(err, InnitError::Unwritable { text: "a\"b".into(), reason: "keys can't contain double quotes when values are quoted" });
+        assert!(IniParser::new().quoted_values(true).parse("a\"b = c").is_err());
+        assert!(document.is_empty());
+        assert_eq!(document.try_insert_validated("a\"b", "c", "section1", &plain), Ok(None));
+        assert_eq!(IniDocument::from_string(document.to_string()), Ok(document.clone()));
+        document.clear();
+
+        // anything that's accepted can be read back
+        let err = document.try_insert_validated("foo", "bar", "two\nlines", &plain).unwrap_err();
+        assert_eq!(err, InnitError::Unwritable { text: "two\nlines".into(), reason: "section names can't contain line breaks" });
+        assert_eq!(document.try_insert_validated("foo", "a = b [c]", "weird]\\section", &plain), Ok(None));
+        assert_eq!(document.try_insert_validated("foo", "changed", "weird]\\section", &plain), Ok(Some("a = b [c]".into())));
+        assert_eq!(document.try_insert_validated("foo", "bar", " padded\t", &plain), Ok(None));
+        assert_eq!(document.try_insert_validated("foo", "bar", "\\ ", &plain), Ok(None));
+        assert!(document.to_string().contains("[\\ padded\\\t]"));
+        assert_eq!(IniDocument::from_string(document.to_string()), Ok(document.clone()));
+
+        let quoted = FormatOptions { quoted_keys: true, quoted_values: true, ..Default::default() };
+        for (key, value) in [("a=b", " padded "), ("# comment", "two\nlines"), ("[header]", "")] {
+            assert_eq!(document.try_insert_validated(key, value, "section1", &quoted), Ok(None));
+        }
+        let parser = IniParser::new().quoted_keys(true).quoted_values(true);
+        assert_eq!(parser.parse(document.to_string_with(&quoted)), Ok(document));
     }
 
-    #[cfg(feature = "case_insensitive")]
     #[test]
-    fn ci() {
+    fn append() {
+        let mut document = IniDocument::empty();
+        document.append("PATH", "", "/bin", ":");
+        assert_eq!(document.get("PATH", ""), Some("/bin"));
+        document.append("PATH", "", "/sbin", ":");
+        assert_eq!(document.get("PATH", ""), Some("/bin:/sbin"));
+
+        document.insert("flags", "-a", "section1");
+        document.append("flags", "section1", String::from("-b"), " ");
+        assert_eq!(document.get("flags", "section1"), Some("-a -b"));
+        assert_eq!(document.len(), 2);
+    }
+
+    #[test]
+    fn insert_section() {
+        let ini = r"foo = bar
+[section1]
+foo = baz
+baz = bop";
+        let mut document = IniDocument::from_string(ini).unwrap();
+        document.set_comment("foo", "section1", "comment");
+
+        document.insert_section("section1", vec![("qux".to_string(), "quux".to_string())]);
+        assert_eq!(document.get("qux", "section1"), Some("quux"));
+        assert_eq!(document.get("foo", "section1"), None);
+        assert_eq!(document.key_count("section1"), Some(1));
+        assert_eq!(document.get("foo", ""), Some("bar"));
+
+        document.insert("foo", "baz", "section1");
+        assert_eq!(document.get_comment("foo", "section1"), None);
+
+        document.insert_section("new", [("a", "1"), ("b", "2")]);
+        assert_eq!(document.get("b", "new"), Some("2"));
+
+        document.insert_section("empty", Vec::<(String, String)>::new());
+        assert_eq!(document.key_count("empty"), Some(0));
+    }
+
+    #[test]
+    fn extend_section() {
+        let ini = r"[section1]
+foo = baz
+baz = bop";
+        let mut document = IniDocument::from_string(ini).unwrap();
+
+        document.extend_section("section1", [("baz", "qux"), ("new", "value")]);
+        assert_eq!(document.get("foo", "section1"), Some("baz"));
+        assert_eq!(document.get("baz", "section1"), Some("qux"));
+        assert_eq!(document.get("new", "section1"), Some("value"));
+        assert_eq!(document.key_count("section1"), Some(3));
+
+        document.extend_section("section2", vec![("foo".to_string(), "bar".to_string())]);
+        assert_eq!(document.get("foo", "section2"), Some("bar"));
+    }
+
+    #[test]
+    fn from_iter() {
+        let document: IniDocument = vec![
+            ("foo".to_string(), "bar".to_string(), "".to_string()),
+            ("foo".to_string(), "baz".to_string(), "section1".to_string()),
+            ("baz".to_string(), "bop".to_string(), "section1".to_string()),
+            ("foo".to_string(), "qux".to_string(), "section1".to_string()),
+        ].into_iter().collect();
+        assert_eq!(document.get("foo", ""), Some("bar"));
+        assert_eq!(document.get("foo", "section1"), Some("qux"));
+        assert_eq!(document.len(), 3);
+
+        let back = document.to_string_sorted();
+        assert_eq!(back, ["foo = bar", "[section1]", "baz = bop", "foo = qux", ""].join(LINE_DELIM));
+        assert_eq!(IniDocument::from_string(back).unwrap(), document);
+    }
+
+    #[test]
+    fn iterate() {
+        let document = IniDocument::from_string("foo = bar\n[section1]\nbaz = bop\nqux = quux").unwrap();
+        let mut borrowed = Vec::new();
+        for (k, v, section) in &document {
+            borrowed.push((k, v, section))
+        }
+        borrowed.sort();
+        assert_eq!(borrowed, [("baz", "bop", "section1"), ("foo", "bar", ""), ("qux", "quux", "section1")]);
+        assert_eq!(document.iter().count(), document.len());
+
+        let mut owned: Vec<(String, String, String)> = document.clone().into_iter().collect();
+        owned.sort();
+        assert_eq!(owned, [
+            ("baz".to_string(), "bop".to_string(), "section1".to_string()),
+            ("foo".to_string(), "bar".to_string(), "".to_string()),
+            ("qux".to_string(), "quux".to_string(), "section1".to_string()),
+        ]);
+        assert_eq!(owned.into_iter().collect::<IniDocument>(), document);
+        assert_eq!(IniDocument::empty().into_iter().next(), None);
+    }
+
+    #[test]
+    fn get_list() {
+        let document = IniDocument::from_string("tags = a, b ,c\npath = /bin:/usr/bin::\nempty =\none = solo").unwrap();
+        assert_eq!(document.get_list("tags", "", ','), Some(vec!["a", "b", "c"]));
+        assert_eq!(document.get_list("path", "", ':'), Some(vec!["/bin", "/usr/bin"]));
+        assert_eq!(document.get_list("tags", "", ':'), Some(vec!["a, b ,c"]));
+        assert_eq!(document.get_list("empty", "", ','), Some(vec![]));
+        assert_eq!(document.get_list("one", "", ','), Some(vec!["solo"]));
+        assert_eq!(document.get_list("missing", "", ','), None);
+    }
+
+    #[test]
+    fn sections_with_prefix() {
+        let ini = "top = 1\n[service:web]\nport = 80\n[service:db]\nport = 5432\n[services]\nenabled = yes\n[logging]\nlevel = info";
+        let document = IniDocument::from_string(ini).unwrap();
+        let mut found: Vec<(&str, Option<&str>)> = document.sections_with_prefix("service:").map(|(name, s)| (name, s.get("port"))).collect();
+        found.sort();
+        assert_eq!(found, [("service:db", Some("5432")), ("service:web", Some("80"))]);
+        assert_eq!(document.sections_with_prefix("service").count(), 3);
+        assert_eq!(document.sections_with_prefix("").count(), document.section_count());
+        assert_eq!(document.sections_with_prefix("Service:").count(), 0);
+    }
+
+    #[test]
+    fn value_is() {
+        let document = IniDocument::from_string("mode = release\n[app]\nmode = debug\nempty =").unwrap();
+        assert!(document.value_is("mode", "app", "debug"));
+        assert!(document.value_is("mode", "", "release"));
+        assert!(document.value_is("empty", "app", ""));
+        assert!(!document.value_is("mode", "app", "release"));
+        assert!(!document.value_is("mode", "app", "DEBUG"));
+        assert!(!document.value_is("missing", "app", ""));
+        assert!(!document.value_is("mode", "other", "debug"));
+    }
+
+    #[test]
+    fn get_nested() {
+        let ini = "host = global\n[database]\nhost = db\n[database.primary]\nhost = db1\n[database.replica]\nhost = db2";
+        let document = IniDocument::from_string(ini).unwrap();
+        assert_eq!(document.get_nested("host", &["database", "primary"]), Some("db1"));
+        assert_eq!(document.get_nested("host", &["database", "replica"]), Some("db2"));
+        assert_eq!(document.get_nested("host", &["database"]), Some("db"));
+        assert_eq!(document.get_nested("host", &[]), Some("global"));
+        assert_eq!(document.get_nested("host", &["database", "primary", "extra"]), None);
+        assert_eq!(document.get_nested("port", &["database", "primary"]), None);
+        let path = [String::from("database"), String::from("primary")];
+        assert_eq!(document.get_nested(String::from("host"), &path), Some("db1"));
+    }
+
+    #[test]
+    fn lines() {
+        // the example from the module docs
         let ini = r"foo = bar
 # comment
 ; comment
-BAZ=bop
+baz=bop
 [section1]
 foo = baz";
-        let document = IniDocument::from_string(ini);
-        assert!(document.is_ok());
-        let document = document.unwrap();
+        let mut document = IniDocument::from_string(ini).unwrap();
+        assert_eq!(document.key_line("foo", ""), Some(1));
+        assert_eq!(document.key_line("baz", ""), Some(4));
+        assert_eq!(document.key_line("foo", "section1"), Some(6));
+        assert_eq!(document.section_line("section1"), Some(5));
+        assert_eq!(document.section_line(""), None);
+        assert_eq!(document.key_line("missing", ""), None);
+
+        document.insert("foo", "changed", "section1");
+        document.insert("new", "key", "section1");
+        assert_eq!(document.key_line("foo", "section1"), Some(6));
+        assert_eq!(document.key_line("new", "section1"), None);
+        document.rename_section("section1", "renamed").unwrap();
+        assert_eq!(document.section_line("renamed"), Some(5));
+        document.remove("foo", "");
+        assert_eq!(document.key_line("foo", ""), None);
+
+        let document = IniDocument::from_string("[a]\nx = 1\n[b]\nx = 2\n[a]\nx = 3\n[empty]").unwrap();
+        assert_eq!(document.key_line("x", "a"), Some(6));
+        assert_eq!(document.section_line("a"), Some(1));
+        assert_eq!(document.section_line("b"), Some(3));
+        assert_eq!(document.section_line("empty"), None);
+    }
+
+    #[test]
+    fn find() {
+        let document = IniDocument::from_string("password = global\n[db]\npassword = hunter2\nuser = admin\n[cache]\nhost = localhost\n[ldap]\npassword = swordfish").unwrap();
+        let mut found = document.find(|section, key, _| key == "password" && section != "");
+        found.sort();
+        assert_eq!(found, [("db", "password", "hunter2"), ("ldap", "password", "swordfish")]);
+        assert_eq!(document.find(|_, _, value| value.contains("host")), [("cache", "host", "localhost")]);
+        assert!(document.find(|_, _, _| false).is_empty());
+    }
+
+    #[test]
+    fn map_values() {
+        let ini = "foo = bar\n[section1]\nfoo = baz\nitems[] = one\nitems[] = two";
+        let mut document = IniParser::new().array_keys(true).parse(ini).unwrap();
+        let mut seen = Vec::new();
+        document.map_values(|section, key, value| {
+            seen.push((section.to_string(), key.to_string()));
+            value.to_uppercase()
+        });
+        seen.sort();
+        assert_eq!(seen, [("".into(), "foo".into()), ("section1".into(), "foo".into()), ("section1".into(), "items".into()), ("section1".into(), "items".into())]);
+        assert_eq!(document.get("foo", ""), Some("BAR"));
+        assert_eq!(document.get("foo", "section1"), Some("BAZ"));
+        assert_eq!(document.get_all("items", "section1"), Some(&["ONE".to_string(), "TWO".to_string()][..]));
+        assert_eq!(document.section_count(), 2);
+        assert_eq!(document.len(), 4);
+    }
+
+    #[test]
+    fn from_hashmap() {
+        let mut sections = HashMap::new();
+        sections.insert("".to_string(), HashMap::from([("foo".to_string(), "bar".to_string())]));
+        sections.insert("section1".to_string(), HashMap::from([("foo".to_string(), "baz".to_string())]));
+        sections.insert("empty".to_string(), HashMap::new());
+        let document = IniDocument::from(sections);
+
+        let mut expected = IniDocument::empty();
+        expected.insert("foo", "bar", "");
+        expected.insert("foo", "baz", "section1");
+        expected.insert_section("empty", Vec::<(String, String)>::new());
+        assert_eq!(document, expected);
+        assert_eq!(document.to_string_sorted(), ["foo = bar", "[empty]", "[section1]", "foo = baz", ""].join(LINE_DELIM));
+    }
+
+    #[test]
+    fn get_or() {
+        let document = IniDocument::from_string("foo = bar").unwrap();
+        assert_eq!(document.get_or("foo", "", "fallback"), "bar");
+        assert_eq!(document.get_or("baz", "", "fallback"), "fallback");
+        assert_eq!(document.get_owned_or("foo", "", "fallback"), "bar");
+        assert_eq!(document.get_owned_or("foo", "section1", String::from("fallback")), "fallback");
+    }
+
+    #[test]
+    fn get_trimmed() {
+        let mut document = IniDocument::empty();
+        document.insert("foo", "  bar ", "");
+        assert_eq!(document.get("foo", ""), Some("  bar "));
+        assert_eq!(document.get_trimmed("foo", ""), Some("bar"));
+        assert_eq!(document.get_trimmed("baz", ""), None);
+    }
+
+    #[test]
+    fn keys_and_values() {
+        let document = IniDocument::from_string("foo = bar\n[section1]\nbaz = bop\nqux = quux").unwrap();
+        let mut keys: Vec<&str> = document.keys("section1").unwrap().collect();
+        keys.sort();
+        assert_eq!(keys, ["baz", "qux"]);
+        let mut values: Vec<&str> = document.values("section1").unwrap().collect();
+        values.sort();
+        assert_eq!(values, ["bop", "quux"]);
+        assert!(document.keys("section2").is_none());
+        assert!(document.values("section2").is_none());
+    }
+
+    #[test]
+    fn equality() {
+        let mut a = IniDocument::empty();
+        a.insert("foo", "bar", "");
+        a.insert("baz", "bop", "section1");
+        a.insert("qux", "quux", "section1");
+        a.set_comment("foo", "", "a comment");
+        let mut b = IniDocument::empty();
+        b.insert("qux", "quux", "section1");
+        b.insert("baz", "bop", "section1");
+        b.insert("foo", "bar", "");
+        assert_ne!(a, b);
+        b.set_comment("foo", "", "a comment");
+        assert_eq!(a, b);
+
+        // leftovers from things that have been removed don't count
+        b.set_comment("baz", "section1", "removed");
+        b.remove_comment("baz", "section1");
+        b.push("items", "one", "section1");
+        b.remove_all("items", "section1");
+        assert_eq!(a, b);
+        assert_eq!(a, IniParser::new().preserve_formatting(true).parse(a.to_string()).unwrap());
+    }
+
+    #[test]
+    fn with_capacity() {
+        let mut a = IniDocument::empty();
+        let mut b = IniDocument::with_capacity(100);
+        assert_eq!(a, b);
+        assert!(b.is_empty());
+        for document in [&mut a, &mut b] {
+            for i in 0..100 {
+                document.insert("key", i.to_string(), format!("section{}", i));
+            }
+            let mut section = Section::with_capacity(10);
+            section.extend((0..10).map(|i| (format!("key{}", i), i.to_string())));
+            document.insert_section("", section.into_map());
+        }
+        assert_eq!(a, b);
+        assert_eq!(a.to_string_sorted(), b.to_string_sorted());
+        assert_eq!(b.get("key", "section42"), Some("42"));
+        assert_eq!(b.get("key7", ""), Some("7"));
+    }
+
+    #[test]
+    fn counts() {
+        let ini = r"foo = bar
+# comment
+; comment
+baz=bop
+[section1]
+foo = baz";
+        let document = IniDocument::from_string(ini).unwrap();
+        assert_eq!(document.len(), 3);
+        assert_eq!(document.section_count(), 2);
+        assert_eq!(document.key_count(""), Some(2));
+        assert_eq!(document.key_count("section1"), Some(1));
+        assert_eq!(document.key_count("section2"), None);
+
+        let mut document = document;
+        document.push("items", "a", "section1");
+        document.push("items", "b", "section1");
+        assert_eq!(document.key_count("section1"), Some(3));
+        assert_eq!(document.len(), document.key_count("").unwrap() + document.key_count("section1").unwrap());
+
+        let empty = IniDocument::empty();
+        assert_eq!(empty.len(), 0);
+        assert_eq!(empty.section_count(), 0);
+    }
+
+    #[test]
+    fn clear() {
+        let ini = r"foo = bar
+[section1]
+foo = baz";
+        let mut document = IniDocument::from_string(ini).unwrap();
+        assert!(!document.is_empty());
+        document.clear();
+        assert!(document.is_empty());
+        assert_eq!(document.get_section("section1"), None);
+    }
+
+    #[test]
+    fn clear_global() {
+        let ini = "# comment\nfoo = bar\n[section1]\nfoo = baz";
+        let mut document = IniDocument::from_string(ini).unwrap();
+        let removed = document.remove_section("").unwrap();
+        assert_eq!(removed.get("foo"), Some("bar"));
+        assert_eq!(document.remove_section(""), None);
+
+        let mut cleared = IniDocument::from_string(ini).unwrap();
+        assert_eq!(cleared.clear_global(), removed);
+        assert_eq!(cleared, document);
+        assert_eq!(cleared.get_comment("foo", ""), None);
+        assert!(cleared.clear_global().is_empty());
+        assert_eq!(cleared.get("foo", "section1"), Some("baz"));
+    }
+
+    #[test]
+    fn clear_section() {
+        let ini = r"foo = bar
+[section1]
+foo = baz
+baz = bop";
+        let mut document = IniDocument::from_string(ini).unwrap();
+        document.clear_section("section1");
+        assert_eq!(document.get("foo", "section1"), None);
+        assert_eq!(document.get_section("section1").map(|s| s.len()), Some(0));
+        assert_eq!(document.get("foo", ""), Some("bar"));
+        assert!(document.to_string().contains("[section1]"));
+    }
+
+    #[test]
+    fn retain() {
+        let ini = r"foo = bar
+# the port
+port = 8080
+[section1]
+a = 1
+b = two
+c = 3
+[section2]
+d = four";
+        let mut document = IniDocument::from_string(ini).unwrap();
+        let numeric = |_: &str, v: &str| v.parse::<i64>().is_ok();
+        document.retain_keys("", numeric);
+        document.retain_keys("section1", numeric);
+        document.retain_keys("section2", numeric);
+        document.retain_keys("section3", numeric);
+        assert_eq!(document.get("foo", ""), None);
+        assert_eq!(document.get_comment("port", ""), Some("the port"));
+        let mut keys: Vec<&str> = document.keys("section1").unwrap().collect();
+        keys.sort();
+        assert_eq!(keys, ["a", "c"]);
+        assert_eq!(document.key_count("section2"), Some(0));
+
+        document.remove_empty_sections();
+        assert_eq!(document.section_count(), 2);
+        assert!(document.get_section("section2").is_none());
+
+        document.retain_sections(|name, _| name != "");
+        assert_eq!(document.get("port", ""), None);
+        assert_eq!(document.get_comment("port", ""), None);
+        assert_eq!(document.section_count(), 1);
+    }
+
+    #[test]
+    fn rename_section() {
+        let ini = r"foo = bar
+[old]
+foo = baz
+[other]
+foo = bop";
+        let mut document = IniDocument::from_string(ini).unwrap();
+
+        assert_eq!(document.rename_section("old", "new"), Ok(()));
+        assert_eq!(document.get("foo", "new"), Some("baz"));
+        assert_eq!(document.get_section("old"), None);
+
+        assert_eq!(document.rename_section("new", "other"), Err(InnitError::SectionExists("other".into())));
+        assert_eq!(document.get("foo", "new"), Some("baz"));
+        assert_eq!(document.get("foo", "other"), Some("bop"));
+
+        assert_eq!(document.rename_section("new", ""), Err(InnitError::EmptySectionName));
+        assert_eq!(document.rename_section("missing", "new2"), Err(InnitError::NoSuchSection("missing".into())));
+    }
+
+    #[test]
+    fn rename_key() {
+        let ini = r"foo = bar
+baz = bop";
+        let mut document = IniDocument::from_string(ini).unwrap();
+
+        assert_eq!(document.rename_key("foo", "qux", ""), None);
+        assert_eq!(document.get("qux", ""), Some("bar"));
+        assert_eq!(document.get("foo", ""), None);
+
+        assert_eq!(document.rename_key("qux", "baz", ""), Some("bop".into()));
+        assert_eq!(document.get("baz", ""), Some("bar"));
+        assert_eq!(document.get("qux", ""), None);
+
+        assert_eq!(document.rename_key("missing", "baz", ""), None);
+        assert_eq!(document.get("baz", ""), Some("bar"));
 
-        assert_eq!(document.get_case_insensitive("FOO", ""), Some("bar"));
-        assert_eq!(document.get_case_insensitive("baz", ""), Some("bop"));
-        assert_eq!(document.get_case_insensitive("foo", "SECtion1"), Some("baz"));
+        document.set_comment("baz", "", "comment");
+        document.rename_key("baz", "foo", "");
+        assert_eq!(document.get_comment("foo", ""), Some("comment"));
+        assert_eq!(document.get_comment("baz", ""), None);
     }
 }
@@ -0,0 +1,349 @@
+//! Combining several documents into one.
+
+use super::*;
+
+/// What to do when a key being merged in already exists in the target document.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum MergeStrategy {
+    /// Replace the existing value with the incoming one.
+    #[default]
+    Overwrite,
+    /// Keep the existing value and drop the incoming one.
+    KeepExisting,
+}
+
+/// The order in which documents passed to [`IniDocument::merge_all_with_priority`] are consulted, optionally on a per-section basis.
+/// 
+/// Orders are lists of indices into the slice of documents, highest priority first.
+/// Any documents missing from an order are consulted after the listed ones, with later documents taking priority over earlier ones,
+/// just like [`IniDocument::merge_all`].
+/// Indices that are out of range are ignored.
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct MergePriority {
+    default: Vec<usize>,
+    sections: HashMap<String, Vec<usize>>,
+}
+impl MergePriority {
+    /// Create a new `MergePriority` that behaves like plain last-wins merging until orders are added.
+    pub fn new() -> MergePriority {
+        MergePriority::default()
+    }
+    /// Set the order used for sections that don't have their own.
+    pub fn default_order(mut self, order: Vec<usize>) -> MergePriority {
+        self.default = order;
+        self
+    }
+    /// Set the order used for a single section.
+    pub fn section<T: Into<String>>(mut self, section: T, order: Vec<usize>) -> MergePriority {
+        self.sections.insert(section.into(), order);
+        self
+    }
+
+    /// the full order for a section, highest priority first, given the number of documents
+    fn order_for(&self, section: &str, count: usize) -> Vec<usize> {
+        let listed = self.sections.get(section).unwrap_or(&self.default);
+        let mut order = Vec::with_capacity(count);
+        for &i in listed {
+            if i < count && !order.contains(&i) {
+                order.push(i)
+            }
+        }
+        for i in (0..count).rev() {
+            if !order.contains(&i) {
+                order.push(i)
+            }
+        }
+        order
+    }
+}
+
+impl IniDocument {
+    /// Merge another document into this one, resolving keys that exist in both according to `strategy`.
+    pub fn merge(&mut self, other: &IniDocument, strategy: MergeStrategy) {
+        for name in other.sections.keys() {
+            self.merge_section(other, name, strategy)
+        }
+    }
+    /// Merge a single section from another document into this one, creating it if it doesn't exist,
+    /// and resolving keys that exist in both according to `strategy`. The rest of the document is left alone.
+    /// If the other document doesn't have the section, nothing is changed.
+    /// The other document's [global section](IniDocument::set_global_section) is merged into this one's.
+    /// 
+    /// ```
+    /// # use innit::*;
+    /// let defaults = IniDocument::from_string("[logging]\nlevel = info\nfile = app.log\n[server]\nport = 80").unwrap();
+    /// let mut config = IniDocument::from_string("[logging]\nlevel = debug").unwrap();
+    /// config.merge_section(&defaults, "logging", MergeStrategy::KeepExisting);
+    /// assert_eq!(config.get("level", "logging"), Some("debug"));
+    /// assert_eq!(config.get("file", "logging"), Some("app.log"));
+    /// assert_eq!(config.get_section("server"), None);
+    /// ```
+    pub fn merge_section<T: AsRef<str>>(&mut self, other: &IniDocument, section: T, strategy: MergeStrategy) {
+        let (name, data) = match other.sections.get_key_value(other.lookup_section(section.as_ref())) {
+            Some(found) => found,
+            None => return
+        };
+        let target = self.merged_section_name(other, name);
+        let keys: Vec<(&String, String)> = data.0.keys().map(|k| (k, self.stored_name(k.clone()))).collect();
+        let section = self.sections.entry(target.clone()).or_default();
+        let mut merged = Vec::new();
+        for (k, key) in keys {
+            if strategy == MergeStrategy::Overwrite || !section.contains_key(&key) {
+                section.0.insert(key.clone(), data.0[k].clone());
+                merged.push((k, key));
+            }
+        }
+        // comments come along with the values they're attached to
+        for (k, key) in merged {
+            self.put_meta(&key, &target, other.meta.get(name).and_then(|m| m.get(k)).cloned())
+        }
+        if let Some(&line) = other.section_lines.get(name) {
+            self.set_section_line(&target, line)
+        }
+        self.index_section_changed(&target);
+        // array keys are merged whole, rather than value by value
+        if let Some(arrays) = other.arrays.get(name) {
+            let keys: Vec<(String, &Vec<String>)> = arrays.iter().map(|(k, values)| (self.stored_name(k.clone()), values)).collect();
+            let section = self.arrays.entry(target).or_default();
+            for (key, values) in keys {
+                if strategy == MergeStrategy::Overwrite || !section.contains_key(&key) {
+                    section.insert(key, values.clone());
+                }
+            }
+        }
+    }
+    /// Merge several documents into a new one. When a key appears in more than one document, the last one wins.
+    /// 
+    /// The new document takes its settings, like its [global section](IniDocument::set_global_section), from the first one.
+    pub fn merge_all(docs: &[&IniDocument]) -> IniDocument {
+        let mut ret = IniDocument::empty_like(docs);
+        for doc in docs {
+            ret.merge(doc, MergeStrategy::Overwrite)
+        }
+        ret
+    }
+    /// Merge several documents into a new one. When a key appears in more than one document,
+    /// the value is taken from whichever document `priority` ranks highest for that key's section.
+    /// 
+    /// ```
+    /// # use innit::*;
+    /// let defaults = IniDocument::from_string("[server]\nport = 80\n[client]\nretries = 3").unwrap();
+    /// let user = IniDocument::from_string("[server]\nport = 8080\n[client]\nretries = 5").unwrap();
+    /// 
+    /// // the defaults layer owns [server], everything else is last-wins
+    /// let priority = MergePriority::new().section("server", vec![0]);
+    /// let merged = IniDocument::merge_all_with_priority(&[&defaults, &user], &priority);
+    /// assert_eq!(merged.get("port", "server"), Some("80"));
+    /// assert_eq!(merged.get("retries", "client"), Some("5"));
+    /// ```
+    pub fn merge_all_with_priority(docs: &[&IniDocument], priority: &MergePriority) -> IniDocument {
+        let mut ret = IniDocument::empty_like(docs);
+        // every section by the name it'll have in the result, and what it's called in each document that has it
+        let mut sources: Map<String, HashMap<usize, &String>> = Map::new();
+        for (i, doc) in docs.iter().enumerate() {
+            for name in doc.sections.keys() {
+                sources.entry(ret.merged_section_name(doc, name)).or_default().insert(i, name);
+            }
+        }
+        for (target, names) in sources {
+            let mut section = Map::new();
+            let mut meta = Map::new();
+            let mut arrays = Map::new();
+            // lowest priority first, so higher priorities overwrite
+            for i in priority.order_for(&target, docs.len()).into_iter().rev() {
+                let name = match names.get(&i) {
+                    Some(name) => *name,
+                    None => continue
+                };
+                for (k, v) in &docs[i].sections[name].0 {
+                    let key = ret.stored_name(k.clone());
+                    section.insert(key.clone(), v.clone());
+                    match docs[i].meta.get(name).and_then(|m| m.get(k)) {
+                        Some(m) => meta.insert(key, m.clone()),
+                        None => meta.remove(&key)
+                    };
+                }
+                if let Some(data) = docs[i].arrays.get(name) {
+                    arrays.extend(data.iter().map(|(k, v)| (ret.stored_name(k.clone()), v.clone())));
+                }
+            }
+            ret.sections.insert(target.clone(), Section(section));
+            if !arrays.is_empty() {
+                ret.arrays.insert(target.clone(), arrays);
+            }
+            if !meta.is_empty() {
+                ret.meta.insert(target.clone(), meta);
+            }
+            ret.index_section_changed(&target);
+        }
+        ret
+    }
+
+    /// an empty document with the same settings as the first of `docs`
+    fn empty_like(docs: &[&IniDocument]) -> IniDocument {
+        let Some(first) = docs.first() else {
+            return IniDocument::empty()
+        };
+        IniDocument {
+            global_section: first.global_section.clone(),
+            #[cfg(feature = "case_insensitive")]
+            case_folding: first.case_folding,
+            #[cfg(feature = "case_insensitive")]
+            case_insensitivity: first.case_insensitivity,
+            #[cfg(feature = "case_insensitive")]
+            case_index: first.case_index.as_ref().map(|_| Default::default()),
+            #[cfg(feature = "case_insensitive")]
+            name_case: first.name_case,
+            ..Default::default()
+        }
+    }
+    /// the name a section from another document is stored under when it's merged into this one,
+    /// with the other document's global section going into this one's
+    fn merged_section_name(&self, other: &IniDocument, name: &str) -> String {
+        if name == other.global_section {
+            self.global_section.clone()
+        }
+        else {
+            self.stored_name(name.into())
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn merge_strategies() {
+        let mut base = IniDocument::from_string("foo = bar\n[section1]\nfoo = baz").unwrap();
+        let other = IniDocument::from_string("foo = bop\n[section2]\nfoo = qux").unwrap();
+
+        let mut keep = IniDocument::from_string("foo = bar\n[section1]\nfoo = baz").unwrap();
+        keep.merge(&other, MergeStrategy::KeepExisting);
+        assert_eq!(keep.get("foo", ""), Some("bar"));
+        assert_eq!(keep.get("foo", "section2"), Some("qux"));
+
+        base.merge(&other, MergeStrategy::Overwrite);
+        assert_eq!(base.get("foo", ""), Some("bop"));
+        assert_eq!(base.get("foo", "section1"), Some("baz"));
+    }
+
+    #[test]
+    fn merge_section() {
+        let defaults = IniDocument::from_string("top = default\n[logging]\n# how much\nlevel = info\nfile = app.log\n[server]\nport = 80").unwrap();
+        let config = IniDocument::from_string("top = mine\n[logging]\nlevel = debug\n[server]\nport = 8080").unwrap();
+
+        let mut keep = config.clone();
+        keep.merge_section(&defaults, "logging", MergeStrategy::KeepExisting);
+        assert_eq!(keep.get("level", "logging"), Some("debug"));
+        assert_eq!(keep.get_comment("level", "logging"), None);
+        assert_eq!(keep.get("file", "logging"), Some("app.log"));
+        assert_eq!(keep.get("port", "server"), Some("8080"));
+        assert_eq!(keep.get("top", ""), Some("mine"));
+
+        let mut overwrite = config.clone();
+        overwrite.merge_section(&defaults, "logging", MergeStrategy::Overwrite);
+        assert_eq!(overwrite.get("level", "logging"), Some("info"));
+        assert_eq!(overwrite.get_comment("level", "logging"), Some("how much"));
+        assert_eq!(overwrite.get("file", "logging"), Some("app.log"));
+        assert_eq!(overwrite.get("port", "server"), Some("8080"));
+
+        let mut unchanged = config.clone();
+        unchanged.merge_section(&defaults, "missing", MergeStrategy::Overwrite);
+        assert_eq!(unchanged, config);
+        let mut empty = IniDocument::empty();
+        empty.merge_section(&defaults, "server", MergeStrategy::KeepExisting);
+        assert_eq!(empty.to_string(), ["[server]", "port = 80", ""].join(LINE_DELIM));
+    }
+
+    #[test]
+    fn merge_global_sections() {
+        let parser = IniParser::new().global_section("DEFAULT");
+        let mut base = parser.parse("a = 1\n[section1]\nfoo = bar").unwrap();
+        let other = IniDocument::from_string("b = 2\n\n[section2]\nbaz = bop").unwrap();
+        base.merge(&other, MergeStrategy::Overwrite);
+        assert_eq!(base.get("b", "DEFAULT"), Some("2"));
+        assert_eq!(base.section_line("section2"), Some(3));
+        base.apply_string("c = 3").unwrap();
+        assert_eq!(base.get("c", ""), Some("3"));
+        assert_eq!(base.section_count(), 3);
+        let written = base.to_string();
+        assert!(written.lines().all(|line| line.trim() != "[]"));
+        assert_eq!(parser.parse(&written).unwrap(), base);
+
+        // the other way around, the other document's global section is written without a header too
+        let mut plain = IniDocument::from_string("a = 1").unwrap();
+        plain.merge(&base, MergeStrategy::KeepExisting);
+        assert_eq!(plain.get("c", ""), Some("3"));
+        assert_eq!(plain.get_section("DEFAULT"), None);
+
+        for merged in [IniDocument::merge_all(&[&base, &other]), IniDocument::merge_all_with_priority(&[&base, &other], &MergePriority::new())] {
+            assert_eq!(merged.get("a", ""), Some("1"));
+            assert_eq!(merged.get("b", "DEFAULT"), Some("2"));
+            assert!(!merged.to_string().contains("DEFAULT"));
+        }
+    }
+
+    #[test]
+    fn merge_comments() {
+        let mut base = IniDocument::from_string("# base foo\nfoo = bar\n# base baz\nbaz = bop").unwrap();
+        let other = IniDocument::from_string("# other foo\nfoo = qux").unwrap();
+        base.merge(&other, MergeStrategy::Overwrite);
+        assert_eq!(base.get_comment("foo", ""), Some("other foo"));
+        assert_eq!(base.get_comment("baz", ""), Some("base baz"));
+
+        let merged = IniDocument::merge_all_with_priority(&[&base, &other], &MergePriority::new().default_order(vec![0]));
+        assert_eq!(merged.get_comment("foo", ""), Some("other foo"));
+        assert_eq!(merged.get_comment("baz", ""), Some("base baz"));
+    }
+
+    #[test]
+    fn merge_arrays() {
+        let parser = IniParser::new().array_keys(true);
+        let base = parser.parse("items[] = a\nitems[] = b\nother[] = c").unwrap();
+        let other = parser.parse("items[] = d").unwrap();
+
+        let mut keep = base.clone();
+        keep.merge(&other, MergeStrategy::KeepExisting);
+        assert_eq!(keep.get_all("items", ""), Some(&["a".to_string(), "b".to_string()][..]));
+
+        let merged = IniDocument::merge_all(&[&base, &other]);
+        assert_eq!(merged.get_all("items", ""), Some(&["d".to_string()][..]));
+        assert_eq!(merged.get_all("other", ""), Some(&["c".to_string()][..]));
+
+        let merged = IniDocument::merge_all_with_priority(&[&base, &other], &MergePriority::new().default_order(vec![0]));
+        assert_eq!(merged.get_all("items", ""), Some(&["a".to_string(), "b".to_string()][..]));
+    }
+
+    #[test]
+    fn per_section_priority() {
+        let system = IniDocument::from_string("[server]\nport = 80\nhost = a\n[client]\nretries = 1").unwrap();
+        let site = IniDocument::from_string("[server]\nport = 81\n[client]\nretries = 2\ntimeout = 10").unwrap();
+        let user = IniDocument::from_string("[server]\nport = 82\n[client]\nretries = 3").unwrap();
+        let docs = [&system, &site, &user];
+
+        // plain last-wins
+        let merged = IniDocument::merge_all(&docs);
+        assert_eq!(merged.get("port", "server"), Some("82"));
+        assert_eq!(merged.get("retries", "client"), Some("3"));
+
+        // system owns [server], site owns [client]
+        let priority = MergePriority::new()
+            .section("server", vec![0])
+            .section("client", vec![1]);
+        let merged = IniDocument::merge_all_with_priority(&docs, &priority);
+        assert_eq!(merged.get("port", "server"), Some("80"));
+        assert_eq!(merged.get("host", "server"), Some("a"));
+        assert_eq!(merged.get("retries", "client"), Some("2"));
+        assert_eq!(merged.get("timeout", "client"), Some("10"));
+
+        // a default order applies to unlisted sections
+        let priority = MergePriority::new()
+            .default_order(vec![0, 1, 2])
+            .section("client", vec![2]);
+        let merged = IniDocument::merge_all_with_priority(&docs, &priority);
+        assert_eq!(merged.get("port", "server"), Some("80"));
+        assert_eq!(merged.get("retries", "client"), Some("3"));
+        assert_eq!(merged.get("timeout", "client"), Some("10"));
+    }
+}
@@ -0,0 +1,1300 @@
+//! Parsing documents, with configurable options.
+
+use super::*;
+use std::borrow::Cow;
+use std::collections::HashSet;
+
+/// A configurable INI parser.
+/// 
+/// [`IniDocument::from_string`] uses a parser with the default settings, which accepts everything innit has always accepted.
+/// Options are set with builder-style methods:
+/// 
+/// ```
+/// # use innit::*;
+/// let parser = IniParser::new().require_sections(true);
+/// assert!(parser.parse("[section1]\nfoo = bar").is_ok());
+/// assert!(parser.parse("foo = bar").is_err());
+/// ```
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct IniParser {
+    require_sections: bool,
+    empty_section: EmptySectionPolicy,
+    duplicate_sections: DuplicateSectionPolicy,
+    empty_value: EmptyValuePolicy,
+    bare_keys: bool,
+    comment_prefixes: Vec<String>,
+    delimiters: Vec<char>,
+    quoted_values: bool,
+    quoted_keys: bool,
+    array_keys: bool,
+    preserve_formatting: bool,
+    preserve_comments: bool,
+    preserve_indentation: bool,
+    inline_comments: bool,
+    trim_keys: bool,
+    trim_values: bool,
+    trim_section_names: bool,
+    max_sections: Option<usize>,
+    max_keys: Option<usize>,
+    max_line_length: Option<usize>,
+    reject_control_characters: bool,
+    global_section: String,
+    merge_strategy: MergeStrategy,
+    #[cfg(feature = "case_insensitive")]
+    pub(crate) case_insensitivity: Option<CaseInsensitivity>,
+    #[cfg(feature = "case_insensitive")]
+    pub(crate) name_case: NameCase,
+}
+impl Default for IniParser {
+    fn default() -> IniParser {
+        IniParser {
+            require_sections: false,
+            empty_section: EmptySectionPolicy::default(),
+            duplicate_sections: DuplicateSectionPolicy::default(),
+            empty_value: EmptyValuePolicy::default(),
+            bare_keys: false,
+            comment_prefixes: vec!["#".into(), ";".into()],
+            delimiters: vec!['='],
+            quoted_values: false,
+            quoted_keys: false,
+            array_keys: false,
+            preserve_formatting: false,
+            preserve_comments: false,
+            preserve_indentation: false,
+            inline_comments: false,
+            trim_keys: true,
+            trim_values: true,
+            trim_section_names: true,
+            max_sections: None,
+            max_keys: None,
+            max_line_length: None,
+            reject_control_characters: false,
+            global_section: String::new(),
+            merge_strategy: MergeStrategy::default(),
+            #[cfg(feature = "case_insensitive")]
+            case_insensitivity: None,
+            #[cfg(feature = "case_insensitive")]
+            name_case: NameCase::default(),
+        }
+    }
+}
+
+/// What the parser does with a section header that has the empty string as its name, ie. `[]`.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum EmptySectionPolicy {
+    /// Return [`InnitError::EmptyStringSection`].
+    #[default]
+    Error,
+    /// Skip the header, so the following keys stay in whatever section came before it.
+    Ignore,
+    /// Treat the header as going back to the unnamed section, so the following keys go there.
+    Global,
+}
+
+/// What the parser does with a section header for a section that already had a header earlier in the document.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum DuplicateSectionPolicy {
+    /// Add the keys under the later header to the same section, so both bodies end up merged together.
+    #[default]
+    Merge,
+    /// Return [`InnitError::DuplicateSection`].
+    Error,
+}
+
+/// What the parser does with a key that has nothing but whitespace after its delimiter, like `key = `.
+/// Keys without a delimiter, allowed by [`IniParser::bare_keys`], are always kept.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum EmptyValuePolicy {
+    /// Store the key with the empty string as its value.
+    #[default]
+    Keep,
+    /// Leave the key out, as if it wasn't set. If it was set earlier in the section, that value is kept.
+    Skip,
+    /// Return [`InnitError::EmptyValue`].
+    Error,
+}
+
+/// Something odd about a document that didn't stop it from being parsed, as returned by [`IniParser::parse_with_warnings`].
+#[derive(Debug, Error, Clone, PartialEq, Eq)]
+pub enum Warning {
+    /// A key appeared more than once in the same section, so its earlier value was replaced.
+    #[error("duplicate key `{key}` in section `{section}` on line {line}")]
+    DuplicateKey {
+        /// The section the key is in.
+        section: String,
+        /// The key.
+        key: String,
+        /// The line of the later value, which replaced the earlier one.
+        line: usize,
+    },
+}
+
+impl IniParser {
+    /// Create a new parser with the default settings.
+    pub fn new() -> IniParser {
+        IniParser::default()
+    }
+    /// Require every key to be inside a named section, so that keys before the first section header are an error.
+    /// Off by default.
+    pub fn require_sections(mut self, require: bool) -> IniParser {
+        self.require_sections = require;
+        self
+    }
+    /// Set what to do with section headers that have the empty string as their name. Defaults to [`EmptySectionPolicy::Error`].
+    pub fn empty_section(mut self, policy: EmptySectionPolicy) -> IniParser {
+        self.empty_section = policy;
+        self
+    }
+    /// Set what to do with a section header for a section that's already had one. Defaults to [`DuplicateSectionPolicy::Merge`].
+    pub fn duplicate_sections(mut self, policy: DuplicateSectionPolicy) -> IniParser {
+        self.duplicate_sections = policy;
+        self
+    }
+
+    /// Set what to do with keys that have an empty value, like `key = `. Defaults to [`EmptyValuePolicy::Keep`].
+    /// 
+    /// Quoted empty values like `key = ""` are never empty here, since the quotes show the value is meant to be blank.
+    pub fn empty_value(mut self, policy: EmptyValuePolicy) -> IniParser {
+        self.empty_value = policy;
+        self
+    }
+
+    /// Set what [`parse_onto`](IniParser::parse_onto) does with keys that are already in the document. Defaults to [`MergeStrategy::Overwrite`].
+    /// 
+    /// ```
+    /// # use innit::*;
+    /// let mut config = IniDocument::from_string("[server]\nport = 8080").unwrap();
+    /// let parser = IniParser::new().merge_strategy(MergeStrategy::KeepExisting);
+    /// parser.parse_onto(&mut config, "[server]\nport = 80\nhost = localhost").unwrap();
+    /// assert_eq!(config.get("port", "server"), Some("8080"));
+    /// assert_eq!(config.get("host", "server"), Some("localhost"));
+    /// ```
+    pub fn merge_strategy(mut self, strategy: MergeStrategy) -> IniParser {
+        self.merge_strategy = strategy;
+        self
+    }
+
+    /// Set the name of the section that keys before the first section header go in. Defaults to the empty string.
+    /// 
+    /// The document remembers the name, see [`IniDocument::set_global_section`], so the section is written without a header.
+    /// A header with the same name, like the `[DEFAULT]` section in files for Python's `configparser`, adds to the same section.
+    /// 
+    /// ```
+    /// # use innit::*;
+    /// let document = IniParser::new().global_section("DEFAULT").parse("foo = bar\n[section1]\nfoo = baz").unwrap();
+    /// assert_eq!(document.get("foo", "DEFAULT"), Some("bar"));
+    /// // the empty string still stands for the global section
+    /// assert_eq!(document.get("foo", ""), Some("bar"));
+    /// assert_eq!(document.to_string(), IniDocument::from_string("foo = bar\n[section1]\nfoo = baz").unwrap().to_string());
+    /// ```
+    pub fn global_section<T: Into<String>>(mut self, name: T) -> IniParser {
+        self.global_section = name.into();
+        self
+    }
+
+    /// Accept lines with no equals sign as keys with the empty string as their value, instead of returning [`InnitError::MissingEquals`].
+    /// This allows flag-style entries like `debug` on their own. Off by default.
+    /// 
+    /// Use [`FormatOptions::bare_keys`] to write these back out the same way.
+    pub fn bare_keys(mut self, allow: bool) -> IniParser {
+        self.bare_keys = allow;
+        self
+    }
+
+    /// Set the prefixes that start a comment line. Defaults to `#` and `;`.
+    /// 
+    /// Lines starting with anything else are parsed as data, so with `//` as the only prefix, `# foo` is a [`InnitError::MissingEquals`].
+    /// Empty prefixes are ignored, since they would make every line a comment.
+    pub fn comment_prefixes<I, T>(mut self, prefixes: I) -> IniParser
+    where I: IntoIterator<Item = T>, T: Into<String> {
+        self.comment_prefixes = prefixes.into_iter().map(Into::into).filter(|p: &String| !p.is_empty()).collect();
+        self
+    }
+
+    /// Trim whitespace from around keys. On by default.
+    /// 
+    /// When it's off, everything between the start of the line and the equals sign is kept as the key, including any indentation.
+    pub fn trim_keys(mut self, trim: bool) -> IniParser {
+        self.trim_keys = trim;
+        self
+    }
+    /// Trim whitespace from around values. On by default.
+    /// 
+    /// When it's off, everything after the equals sign is kept as the value, including whitespace at the end of the line.
+    /// With [`quoted_values`](IniParser::quoted_values) on as well, a value is only treated as quoted if the quotes are right next to the equals sign and the end of the line.
+    pub fn trim_values(mut self, trim: bool) -> IniParser {
+        self.trim_values = trim;
+        self
+    }
+    /// Trim whitespace from around section names inside their brackets, so `[ section1 ]` is the section `section1`. On by default.
+    /// 
+    /// When it's off, everything between the brackets is kept as the name, so the same header is the section ` section1 `,
+    /// Either way, whitespace escaped with a backslash, like `[section1\ ]`, is kept,
+    /// which is how section names that start or end with whitespace are written out. Also either way, a header with only whitespace between the brackets is trimmed to the empty string, see [`empty_section`](IniParser::empty_section).
+    pub fn trim_section_names(mut self, trim: bool) -> IniParser {
+        self.trim_section_names = trim;
+        self
+    }
+
+    /// Set the characters that separate keys from values. Defaults to just `=`.
+    /// 
+    /// Each line is split on the first of any of them, so with `=` and `:`, both `key = value` and `key: value` work.
+    /// An empty list is ignored, since no line could be a key/value pair.
+    /// Documents are written out with `=`, apart from unchanged lines kept by [`preserve_formatting`](IniParser::preserve_formatting),
+    /// and changed lines that are regenerated with the delimiter they were parsed with.
+    pub fn delimiters<I: IntoIterator<Item = char>>(mut self, delimiters: I) -> IniParser {
+        let delimiters: Vec<char> = delimiters.into_iter().collect();
+        if !delimiters.is_empty() {
+            self.delimiters = delimiters
+        }
+        self
+    }
+
+    /// Treat values wrapped in double quotes as quoted, so the quotes are removed and the value is kept exactly as written inside them.
+    /// Off by default, which keeps the quotes as part of the value.
+    /// 
+    /// Inside quotes, `\"`, `\\`, `\n`, `\r` and `\t` are escapes for a quote, backslash, newline, carriage return and tab.
+    /// Equals signs inside quotes never split a line into key and value.
+    /// Values that start with a quote but don't end with one are kept as they are.
+    pub fn quoted_values(mut self, quoted: bool) -> IniParser {
+        self.quoted_values = quoted;
+        self
+    }
+    /// Treat keys wrapped in double quotes as quoted, so `"my key" = value` is stored as `my key`, with the same escapes as
+    /// [`quoted_values`](IniParser::quoted_values). Off by default, which keeps the quotes as part of the key.
+    /// 
+    /// Use [`FormatOptions::quoted_keys`] to write keys that need them back out with quotes.
+    pub fn quoted_keys(mut self, quoted: bool) -> IniParser {
+        self.quoted_keys = quoted;
+        self
+    }
+
+    /// Treat keys ending in `[]` as append-style array keys, so each `key[] = value` line adds another value to `key`.
+    /// The values can be got with [`IniDocument::get_all`], and comments above them are not kept.
+    /// Off by default, which keeps the brackets as part of an ordinary key.
+    pub fn array_keys(mut self, arrays: bool) -> IniParser {
+        self.array_keys = arrays;
+        self
+    }
+
+    /// Allow a comment after the closing bracket of a section header, like `[server] ; production settings`.
+    /// The comment can start with any of the [comment prefixes](IniParser::comment_prefixes), and isn't kept in the document,
+    /// though [`preserve_formatting`](IniParser::preserve_formatting) will keep the line as it was.
+    /// Off by default, which makes a header followed by anything else a [`InnitError::MalformedSection`].
+    pub fn inline_comments(mut self, inline: bool) -> IniParser {
+        self.inline_comments = inline;
+        self
+    }
+
+    /// Keep the original text of parsed documents, so that writing them back out with [`to_string`](ToString::to_string)
+    /// only regenerates the lines for keys, values and comments that have changed.
+    /// Every other line, including blank lines, other comments and any odd spacing, is kept exactly as it was.
+    /// New keys are written at the end of their section, and new sections at the end of the document.
+    /// Off by default.
+    /// 
+    /// ```
+    /// # use innit::*;
+    /// let ini = "# the port\nport=80\n\n[paths]\nhome   =   /home\n";
+    /// let mut document = IniParser::new().preserve_formatting(true).parse(ini).unwrap();
+    /// document.insert("port", "8080", "");
+    /// assert_eq!(document.to_string(), "# the port\nport = 8080\n\n[paths]\nhome   =   /home\n");
+    /// ```
+    pub fn preserve_formatting(mut self, preserve: bool) -> IniParser {
+        self.preserve_formatting = preserve;
+        self
+    }
+    /// Keep every comment and blank line where it was when writing parsed documents back out with [`to_string`](ToString::to_string),
+    /// along with the order of sections and keys, but tidy up everything else, so eg. `key=value` is written as `key = value`.
+    /// This is a lighter version of [`preserve_formatting`](IniParser::preserve_formatting), which wins if both are on.
+    /// 
+    /// Off by default. Turning it on keeps every line of the original text in memory with the document, and changes what
+    /// [`to_string`](ToString::to_string) gives for documents that are already being written out, so it's left for callers to opt into.
+    /// 
+    /// ```
+    /// # use innit::*;
+    /// let ini = "# the port\nport=80\n\n[paths]   \n   ; home\nhome   =   /home\n";
+    /// let document = IniParser::new().preserve_comments(true).parse(ini).unwrap();
+    /// assert_eq!(document.to_string(), "# the port\nport = 80\n\n[paths]\n; home\nhome = /home\n");
+    /// ```
+    pub fn preserve_comments(mut self, preserve: bool) -> IniParser {
+        self.preserve_comments = preserve;
+        self
+    }
+    /// Keep the whitespace at the start of each line when writing documents parsed with [`preserve_formatting`](IniParser::preserve_formatting)
+    /// or [`preserve_comments`](IniParser::preserve_comments), for files that indent keys under their sections.
+    /// Changed lines keep the indentation of the line they replace, and new keys get the indentation of the last key in their section.
+    /// Off by default, which writes regenerated lines without any, and has no effect without either of the other options.
+    /// 
+    /// ```
+    /// # use innit::*;
+    /// let ini = "[server]\n\thost = localhost\n\tport = 80\n";
+    /// let mut document = IniParser::new().preserve_formatting(true).preserve_indentation(true).parse(ini).unwrap();
+    /// document.insert("port", "8080", "server");
+    /// assert_eq!(document.to_string(), "[server]\n\thost = localhost\n\tport = 8080\n");
+    /// ```
+    pub fn preserve_indentation(mut self, preserve: bool) -> IniParser {
+        self.preserve_indentation = preserve;
+        self
+    }
+
+    /// Limit the number of named sections a document can have, so that going over it is a [`InnitError::TooManySections`]
+    /// at the header of the first section past the limit. Headers for sections that have already had one don't count again.
+    /// Unlimited by default.
+    /// 
+    /// This and the other limits are meant for parsing untrusted input, where a huge file could otherwise use up all the memory.
+    pub fn max_sections(mut self, max: usize) -> IniParser {
+        self.max_sections = Some(max);
+        self
+    }
+    /// Limit the number of keys each section can have, so that going over it is a [`InnitError::TooManyKeys`]
+    /// at the first key past the limit. Keys that are already in the section don't count again, but each value of an array key does.
+    /// Unlimited by default.
+    pub fn max_keys_per_section(mut self, max: usize) -> IniParser {
+        self.max_keys = Some(max);
+        self
+    }
+    /// Limit the length of each line in bytes, not counting the line ending, so that a longer line is a [`InnitError::LineTooLong`].
+    /// Unlimited by default.
+    pub fn max_line_length(mut self, max: usize) -> IniParser {
+        self.max_line_length = Some(max);
+        self
+    }
+
+    /// Reject control characters like NUL in keys, values and section headers with [`InnitError::InvalidCharacter`],
+    /// for parsing files from untrusted sources. Tabs are still allowed, and comments aren't checked.
+    /// Off by default, which keeps them as part of the key or value.
+    pub fn reject_control_characters(mut self, reject: bool) -> IniParser {
+        self.reject_control_characters = reject;
+        self
+    }
+
+    /// Parse a document from a string.
+    /// 
+    /// Comments on the lines directly above a key are kept with that key, see [`IniDocument::get_comment`].
+    /// A UTF-8 byte order mark at the very start of the string is skipped.
+    /// Lines can end with either `\n` or `\r\n`, regardless of the `crlf` feature, which only affects output.
+    pub fn parse<T: AsRef<str>>(&self, s: T) -> Result<IniDocument, InnitError> {
+        self.parse_with_warnings(s).map(|(document, _)| document)
+    }
+    /// Parse a document from a string, like [`parse`](IniParser::parse), and also return anything odd that was found along the way.
+    /// 
+    /// A key appearing twice in the same section isn't an error, and the later value replaces the earlier one,
+    /// but each time it happens there's a [`Warning::DuplicateKey`].
+    /// 
+    /// ```
+    /// # use innit::*;
+    /// let (document, warnings) = IniParser::new().parse_with_warnings("foo = bar\nfoo = baz").unwrap();
+    /// assert_eq!(document.get("foo", ""), Some("baz"));
+    /// assert_eq!(warnings, [Warning::DuplicateKey { section: "".into(), key: "foo".into(), line: 2 }]);
+    /// ```
+    pub fn parse_with_warnings<T: AsRef<str>>(&self, s: T) -> Result<(IniDocument, Vec<Warning>), InnitError> {
+        let s = s.as_ref();
+        let body = s.strip_prefix('\u{FEFF}').unwrap_or(s);
+        let mut document = IniDocument::empty();
+        self.start(&mut document);
+        let mut state = ParseState { section: document.global_section.clone(), ..Default::default() };
+        // spans are into the string as given, so they start after the byte order mark if there is one
+        let mut start = s.len() - body.len();
+        let mut layout = (self.preserve_formatting || self.preserve_comments).then(|| Layout::new(start != 0, !self.preserve_formatting, self.preserve_indentation)
+            .with_quoting(self.quoted_keys, self.quoted_values));
+        for (lnum, raw) in body.split('\n').enumerate() {
+            let line = raw.strip_suffix('\r').unwrap_or(raw);
+            let parsed = self.parse_line_at(&mut document, line, lnum + 1, start..start + line.len(), &mut state)?;
+            if let Some(layout) = &mut layout {
+                layout.record(&document, raw, parsed, &state.section)
+            }
+            start += raw.len() + 1
+        }
+        document.layout = layout.unwrap_or_default();
+
+        Ok((document, state.warnings))
+    }
+    /// Parse a string and layer it onto an existing document, so its keys replace any that are already there,
+    /// the same as they would if they came later in the same file. See [`IniDocument::apply_string`].
+    /// Keys that are already there can be kept instead, see [`merge_strategy`](IniParser::merge_strategy).
+    /// 
+    /// The whole string is parsed before anything is changed, so if it fails to parse, the document is left exactly as it was.
+    pub fn parse_onto<T: AsRef<str>>(&self, document: &mut IniDocument, s: T) -> Result<(), InnitError> {
+        let layer = self.parse(s)?;
+        document.merge(&layer, self.merge_strategy);
+        Ok(())
+    }
+    /// Parse a single line into a document. See [`IniDocument::parse_line`].
+    /// 
+    /// Since each line is parsed on its own, comments are not kept, and error spans are relative to the start of the line.
+    pub fn parse_line(&self, document: &mut IniDocument, line: &str, current_section: &mut String) -> Result<(), InnitError> {
+        self.start(document);
+        let mut state = ParseState { section: std::mem::take(current_section), ..Default::default() };
+        if state.section == "" {
+            state.section = document.global_section.clone()
+        }
+        let ret = self.parse_line_at(document, line, 1, 0..line.len(), &mut state);
+        *current_section = state.section;
+        ret.map(|_| ())
+    }
+    /// get a document ready to be parsed into, with the settings it keeps from the parser
+    fn start(&self, document: &mut IniDocument) {
+        self.prepare(document);
+        if self.global_section != "" {
+            document.set_global_section(self.global_section.as_str())
+        }
+    }
+    fn parse_line_at<'a>(&self, document: &mut IniDocument, line: &'a str, lnum: usize, span: Range<usize>, state: &mut ParseState) -> Result<Parsed<'a>, InnitError> {
+        if let Some(limit) = self.max_line_length.filter(|max| line.len() > *max) {
+            return Err(InnitError::LineTooLong { limit, line: lnum, span })
+        }
+        let untrimmed = line;
+        let line = line.trim();
+        if let Some(ch) = self.control_character(line) {
+            return Err(InnitError::InvalidCharacter { ch, line: lnum, span })
+        }
+        if line.is_empty() {
+            // a blank line separates a comment from whatever comes after it
+            state.comment = None
+        }
+        else if let Some(text) = self.string_is_comment(line) {
+            match &mut state.comment {
+                Some(comment) => {
+                    comment.push('\n');
+                    comment.push_str(text)
+                }
+                None => state.comment = Some(text.into())
+            }
+            return Ok(Parsed::Comment)
+        }
+        else if let Some(name) = string_is_section_start(self.strip_header_comment(line), self.trim_section_names) {
+            state.comment = None;
+            if name == "" {
+                match self.empty_section {
+                    EmptySectionPolicy::Error => return Err(InnitError::EmptyStringSection(line.into(), lnum, span)),
+                    EmptySectionPolicy::Ignore => return Ok(Parsed::Other),
+                    EmptySectionPolicy::Global => state.section = document.global_section.clone(),
+                }
+            }
+            else {
+                state.section = self.section_name(document, name)
+            }
+            document.set_section_line(&state.section, lnum);
+            if !state.headers.insert(state.section.clone()) {
+                if self.duplicate_sections == DuplicateSectionPolicy::Error {
+                    return Err(InnitError::DuplicateSection { name: state.section.clone(), line: lnum, span })
+                }
+            }
+            else if state.section != document.global_section {
+                state.named_sections += 1;
+                if let Some(limit) = self.max_sections.filter(|max| state.named_sections > *max) {
+                    return Err(InnitError::TooManySections { limit, line: lnum, span })
+                }
+            }
+            return Ok(Parsed::Section)
+        }
+        else {
+            let (k, delimiter, raw, empty) = match parse_k_v(untrimmed, self.quoted_values || self.quoted_keys, &self.delimiters) {
+                Some((k, d, v)) => (if self.trim_keys { k.trim() } else { k }, d, v, v.trim().is_empty()),
+                // only a header missing a bracket, since values like `[1, 2]` are fine
+                None if line.starts_with('[') || line.ends_with(']') => return Err(InnitError::MalformedSection { text: line.into(), line: lnum, span }),
+                // bare keys are meant to be empty, so they don't count as empty values
+                None if self.bare_keys => (line, '=', "", false),
+                None => return Err(InnitError::MissingEquals(line.into(), lnum, span))
+            };
+            // a key outside any section is an error even if it would otherwise be skipped
+            if self.require_sections && state.section == document.global_section {
+                return Err(InnitError::KeyOutsideSection { key: k.into(), text: line.into(), line: lnum, span })
+            }
+            if empty && self.empty_value != EmptyValuePolicy::Keep {
+                if self.empty_value == EmptyValuePolicy::Error {
+                    return Err(InnitError::EmptyValue { key: k.trim().into(), line: lnum, span })
+                }
+                state.comment = None;
+                return Ok(Parsed::Other)
+            }
+            // the value always runs to the end of the line, so this is where it starts
+            let value_start = untrimmed.len() - raw.len();
+            let v = if self.trim_values { raw.trim() } else { raw };
+            let v = if self.quoted_values { unquote(v) } else { Cow::Borrowed(v) };
+            if let Some(name) = k.strip_suffix("[]").filter(|_| self.array_keys) {
+                state.comment = None;
+                let name = self.unquote_key(name.trim_end());
+                self.check_key_limit(document, None, lnum, span, state)?;
+                document.push(name.as_ref(), v.into_owned(), state.section.as_str());
+                return Ok(Parsed::ArrayItem(name))
+            }
+            let k = self.key_name(document, self.unquote_key(k), &state.section);
+            self.check_key_limit(document, Some(&k), lnum, span, state)?;
+            if document.insert(k.as_ref(), v.into_owned(), state.section.as_str()).is_some() {
+                state.warnings.push(Warning::DuplicateKey { section: state.section.clone(), key: k.as_ref().into(), line: lnum })
+            }
+            document.set_key_line(k.as_ref(), &state.section, lnum);
+            if let Some(comment) = state.comment.take() {
+                document.set_comment(k.as_ref(), state.section.as_str(), comment);
+            }
+            return Ok(Parsed::Key(k, delimiter, value_start))
+        }
+        Ok(Parsed::Other)
+    }
+}
+
+#[cfg(not(feature = "case_insensitive"))]
+impl IniParser {
+    // names are always case-sensitive without the feature, so they're stored as they are
+    fn prepare(&self, _document: &mut IniDocument) {}
+    fn section_name(&self, _document: &IniDocument, name: Cow<'_, str>) -> String {
+        name.into_owned()
+    }
+    fn key_name<'a>(&self, _document: &IniDocument, key: Cow<'a, str>, _section: &str) -> Cow<'a, str> {
+        key
+    }
+}
+
+/// what the parser needs to remember between lines
+#[derive(Debug, Default)]
+struct ParseState {
+    section: String,
+    /// the comment block directly above the current line
+    comment: Option<String>,
+    warnings: Vec<Warning>,
+    /// every section that's had a header so far
+    headers: HashSet<String>,
+    /// how many of those aren't the unnamed section
+    named_sections: usize,
+}
+
+impl IniParser {
+    /// the first control character in a line that isn't a comment, if they're being rejected
+    fn control_character(&self, line: &str) -> Option<char> {
+        if !self.reject_control_characters || self.string_is_comment(line).is_some() {
+            return None
+        }
+        line.chars().find(|c| c.is_control() && *c != '\t')
+    }
+    fn unquote_key<'a>(&self, key: &'a str) -> Cow<'a, str> {
+        if self.quoted_keys {
+            unquote(key)
+        }
+        else {
+            Cow::Borrowed(key)
+        }
+    }
+    /// errors if adding the key, or another array value if there's no key, would take the current section over the limit
+    fn check_key_limit(&self, document: &IniDocument, key: Option<&str>, lnum: usize, span: Range<usize>, state: &ParseState) -> Result<(), InnitError> {
+        let limit = match self.max_keys {
+            Some(limit) => limit,
+            None => return Ok(())
+        };
+        let section = document.sections.get(&state.section);
+        if key.is_some_and(|k| section.is_some_and(|s| s.contains_key(k))) {
+            return Ok(())
+        }
+        let keys = section.map_or(0, |s| s.len());
+        let values: usize = document.section_arrays(&state.section).map_or(0, |a| a.values().map(|v| v.len()).sum());
+        if keys + values >= limit {
+            return Err(InnitError::TooManyKeys { section: state.section.clone(), limit, line: lnum, span })
+        }
+        Ok(())
+    }
+    /// returns the comment text if it is
+    pub(crate) fn string_is_comment<'a>(&self, s: &'a str) -> Option<&'a str> {
+        let text = self.comment_prefixes.iter().find_map(|p| s.strip_prefix(p.as_str()))?;
+        Some(text.strip_prefix(' ').unwrap_or(text))
+    }
+    /// returns the section header without its trailing comment, if inline comments are on and it has one
+    fn strip_header_comment<'a>(&self, s: &'a str) -> &'a str {
+        if !self.inline_comments || !s.starts_with('[') {
+            return s
+        }
+        // the first closing bracket that isn't escaped
+        let mut escaped = false;
+        let end = s.char_indices().find(|&(_, c)| {
+            match c {
+                _ if escaped => escaped = false,
+                '\\' => escaped = true,
+                ']' => return true,
+                _ => ()
+            }
+            false
+        });
+        match end {
+            Some((i, _)) if self.string_is_comment(s[i + 1..].trim_start()).is_some() => &s[..=i],
+            _ => s
+        }
+    }
+}
+/// returns Some if it is
+/// 
+/// the name is trimmed if `trim` is set or it's only whitespace, and then unescaped, see [`escape_section_name`]
+pub(crate) fn string_is_section_start(s: &str, trim: bool) -> Option<Cow<'_, str>> {
+    let mut inner = s.strip_prefix('[')?.strip_suffix(']')?;
+    if trim || inner.trim().is_empty() {
+        inner = trim_escaped(inner)
+    }
+    if !inner.contains('\\') {
+        return Some(Cow::Borrowed(inner))
+    }
+    let mut ret = String::with_capacity(inner.len());
+    let mut chars = inner.chars();
+    while let Some(c) = chars.next() {
+        if c == '\\' {
+            match chars.next() {
+                Some(c) if matches!(c, '\\' | ']') || c.is_whitespace() => ret.push(c),
+                // keep unknown escapes as they are, so names like `C:\new` still work,
+                // and a backslash right before the closing bracket is part of the name, like `[dir\]`
+                Some(c) => {
+                    ret.push('\\');
+                    ret.push(c)
+                }
+                None => ret.push('\\')
+            }
+        }
+        else {
+            ret.push(c)
+        }
+    }
+    Some(Cow::Owned(ret))
+}
+/// trim whitespace from either end, except whitespace escaped with a backslash
+fn trim_escaped(s: &str) -> &str {
+    let s = s.trim_start();
+    let end = s.trim_end();
+    match s[end.len()..].chars().next() {
+        // an odd number of backslashes means the first whitespace character after them is escaped
+        Some(c) if end.chars().rev().take_while(|&c| c == '\\').count() % 2 == 1 => &s[..end.len() + c.len_utf8()],
+        _ => end
+    }
+}
+/// split on the first delimiter, ignoring any inside quotes if `quoted` is set. neither side is trimmed
+pub(crate) fn parse_k_v<'a>(s: &'a str, quoted: bool, delimiters: &[char]) -> Option<(&'a str, char, &'a str)> {
+    let split = if quoted {
+        let mut in_quotes = false;
+        let mut escaped = false;
+        let i = s.char_indices().find(|&(_, c)| {
+            match c {
+                _ if escaped => escaped = false,
+                '\\' if in_quotes => escaped = true,
+                '"' => in_quotes = !in_quotes,
+                c if !in_quotes && delimiters.contains(&c) => return true,
+                _ => ()
+            }
+            false
+        })?.0;
+        i
+    }
+    else {
+        s.find(delimiters)?
+    };
+    let delimiter = s[split..].chars().next()?;
+    Some((&s[..split], delimiter, &s[split + delimiter.len_utf8()..]))
+}
+/// remove quotes from around a value and handle escapes inside them, if it's quoted
+fn unquote(s: &str) -> Cow<'_, str> {
+    let inner = match s.strip_prefix('"').and_then(|s| s.strip_suffix('"')) {
+        Some(inner) => inner,
+        None => return Cow::Borrowed(s)
+    };
+    let mut ret = String::with_capacity(inner.len());
+    let mut chars = inner.chars();
+    while let Some(c) = chars.next() {
+        if c == '\\' {
+            match chars.next() {
+                Some('n') => ret.push('\n'),
+                Some('r') => ret.push('\r'),
+                Some('t') => ret.push('\t'),
+                Some(c) => ret.push(c),
+                None => ret.push('\\')
+            }
+        }
+        else {
+            ret.push(c)
+        }
+    }
+    Cow::Owned(ret)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn require_sections() {
+        let ini = r"foo = bar
+[section1]
+foo = baz";
+        let document = IniParser::new().parse(ini).unwrap();
+        assert_eq!(document.get("foo", ""), Some("bar"));
+
+        let document = IniParser::new().require_sections(true).parse(ini);
+        assert_eq!(document, Err(InnitError::KeyOutsideSection { key: "foo".into(), text: "foo = bar".into(), line: 1, span: 0..9 }));
+        assert!(document.unwrap_err().to_string().contains("`foo = bar`"));
+
+        let document = IniParser::new().require_sections(true).parse("# comment\n[section1]\nfoo = baz").unwrap();
+        assert_eq!(document.get("foo", "section1"), Some("baz"));
+    }
+
+    #[test]
+    fn empty_section() {
+        let ini = r"foo = bar
+[section1]
+foo = baz
+[]
+baz = bop";
+        let document = IniParser::new().parse(ini);
+        assert_eq!(document, Err(InnitError::EmptyStringSection("[]".into(), 4, 31..33)));
+        assert_eq!(document.unwrap_err().to_string(), "section with empty string as name `[]` on line 4");
+
+        let document = IniParser::new().empty_section(EmptySectionPolicy::Ignore).parse(ini).unwrap();
+        assert_eq!(document.get("baz", "section1"), Some("bop"));
+        assert_eq!(document.get("baz", ""), None);
+
+        let document = IniParser::new().empty_section(EmptySectionPolicy::Global).parse(ini).unwrap();
+        assert_eq!(document.get("baz", ""), Some("bop"));
+        assert_eq!(document.get("baz", "section1"), None);
+    }
+
+    #[test]
+    fn duplicate_sections() {
+        let ini = r"[section1]
+foo = bar
+[section2]
+baz = bop
+[section1]
+qux = quux";
+        let document = IniParser::new().parse(ini).unwrap();
+        let mut keys: Vec<&str> = document.keys("section1").unwrap().collect();
+        keys.sort();
+        assert_eq!(keys, ["foo", "qux"]);
+
+        let parser = IniParser::new().duplicate_sections(DuplicateSectionPolicy::Error);
+        let err = parser.parse(ini).unwrap_err();
+        assert_eq!(err, InnitError::DuplicateSection { name: "section1".into(), line: 5, span: 42..52 });
+        assert_eq!(err.to_string(), "duplicate section `section1` on line 5");
+        assert!(parser.parse("foo = bar\n[section1]\nbaz = bop").is_ok());
+    }
+
+    #[test]
+    fn limits() {
+        let ini = r"[section1]
+a = 1
+b = 2
+a = 3
+[section2]
+a = 1
+b = 2
+c = 3
+[section1]
+c = 3";
+        assert!(IniParser::new().parse(ini).is_ok());
+        assert!(IniParser::new().max_keys_per_section(3).max_sections(2).max_line_length(10).parse(ini).is_ok());
+
+        let err = IniParser::new().max_keys_per_section(2).parse(ini).unwrap_err();
+        assert_eq!(err, InnitError::TooManyKeys { section: "section2".into(), limit: 2, line: 8, span: 52..57 });
+        assert_eq!(err.to_string(), "more than 2 keys in section `section2`, on line 8");
+        assert_eq!(&ini[err.span().unwrap()], "c = 3");
+
+        let parser = IniParser::new().max_keys_per_section(2).array_keys(true);
+        assert!(parser.parse("a = 1\nitems[] = x").is_ok());
+        assert_eq!(parser.parse("a = 1\nitems[] = x\nitems[] = y").unwrap_err().line(), Some(3));
+
+        let err = IniParser::new().max_sections(1).parse(ini).unwrap_err();
+        assert_eq!(err, InnitError::TooManySections { limit: 1, line: 5, span: 29..39 });
+        // the unnamed section doesn't count
+        assert!(IniParser::new().max_sections(1).parse("foo = bar\n[section1]\nbaz = bop").is_ok());
+
+        let err = IniParser::new().max_line_length(5).parse(ini).unwrap_err();
+        assert_eq!(err, InnitError::LineTooLong { limit: 5, line: 1, span: 0..10 });
+        assert_eq!(IniParser::new().max_line_length(9).parse("foo = bar\r\n").map(|d| d.get("foo", "").map(String::from)), Ok(Some("bar".into())));
+    }
+
+    #[test]
+    fn control_characters() {
+        let ini = "[section1]\nfoo = a\0b\nbar = tab\tseparated\n# comment with \x07 in it";
+        let document = IniParser::new().parse(ini).unwrap();
+        assert_eq!(document.get("foo", "section1"), Some("a\0b"));
+
+        let parser = IniParser::new().reject_control_characters(true);
+        let err = parser.parse(ini).unwrap_err();
+        assert_eq!(err, InnitError::InvalidCharacter { ch: '\0', line: 2, span: 11..20 });
+        assert_eq!(err.to_string(), "control character '\\0' on line 2");
+        assert_eq!(parser.parse("[sec\x1btion]").unwrap_err().line(), Some(1));
+        assert_eq!(parser.parse("ke\x7fy = value").unwrap_err().line(), Some(1));
+        assert!(parser.parse("bar = tab\tseparated\r\n# comment with \x07 in it").is_ok());
+    }
+
+    #[test]
+    fn empty_values() {
+        let ini = "[section1]\nkey = set\n# about blank\nkey =\nblank =   \nflag\nquoted = \"\"";
+        let parser = IniParser::new().bare_keys(true).quoted_values(true);
+
+        let document = parser.clone().empty_value(EmptyValuePolicy::Keep).parse(ini).unwrap();
+        assert_eq!(document, parser.parse(ini).unwrap());
+        assert_eq!(document.get("key", "section1"), Some(""));
+        assert_eq!(document.get("blank", "section1"), Some(""));
+
+        let document = parser.clone().empty_value(EmptyValuePolicy::Skip).parse(ini).unwrap();
+        assert_eq!(document.get("key", "section1"), Some("set"));
+        assert_eq!(document.get_comment("key", "section1"), None);
+        assert_eq!(document.get("blank", "section1"), None);
+        assert_eq!(document.get("flag", "section1"), Some(""));
+        assert_eq!(document.get("quoted", "section1"), Some(""));
+
+        let err = parser.clone().empty_value(EmptyValuePolicy::Error).parse(ini).unwrap_err();
+        assert_eq!(err, InnitError::EmptyValue { key: "key".into(), line: 4, span: 35..40 });
+        assert_eq!(err.to_string(), "key `key` has an empty value on line 4");
+        assert!(parser.clone().empty_value(EmptyValuePolicy::Error).parse("flag\nquoted = \"\"").is_ok());
+
+        // skipping an empty value doesn't hide a key outside any section
+        let err = parser.empty_value(EmptyValuePolicy::Skip).require_sections(true).parse("key =\n[section1]").unwrap_err();
+        assert_eq!(err, InnitError::KeyOutsideSection { key: "key".into(), text: "key =".into(), line: 1, span: 0..5 });
+    }
+
+    #[test]
+    fn global_section() {
+        let ini = "foo = bar\nbaz = bop\n[section1]\nfoo = qux\n[DEFAULT]\nlate = yes";
+        let parser = IniParser::new().global_section("DEFAULT");
+        let document = parser.parse(ini).unwrap();
+        assert_eq!(document.global_section(), "DEFAULT");
+        assert_eq!(document.get("foo", "DEFAULT"), Some("bar"));
+        assert_eq!(document.get("baz", "DEFAULT"), Some("bop"));
+        assert_eq!(document.get("late", "DEFAULT"), Some("yes"));
+        assert_eq!(document.get("foo", "section1"), Some("qux"));
+        assert_eq!(document.get_section(""), document.get_section("DEFAULT"));
+
+        let options = FormatOptions { sorted: true, line_ending: LineEnding::Lf, ..Default::default() };
+        assert_eq!(document.to_string_with(&options), "baz = bop\nfoo = bar\nlate = yes\n[section1]\nfoo = qux\n");
+        assert_eq!(parser.parse(document.to_string()).unwrap(), document);
+
+        assert!(parser.clone().require_sections(true).parse("foo = bar").is_err());
+        assert!(parser.max_sections(1).parse(ini).is_ok());
+
+        // the empty string is never written as a `[]` header
+        let mut document = IniDocument::from_string("a = 1\n[section1]\nfoo = bar").unwrap();
+        document.set_global_section("DEFAULT");
+        document.insert("b", "2", "");
+        document.section_mut("").insert("c", "3");
+        document.push("items", "x", "");
+        assert_eq!(document.get_section("DEFAULT").unwrap().len(), 3);
+        assert_eq!(document.section_count(), 2);
+        let written = document.to_string();
+        assert!(written.lines().all(|line| line.trim() != "[]"));
+        assert_eq!(IniParser::new().global_section("DEFAULT").array_keys(true).parse(&written).unwrap(), document);
+        assert_eq!(document.remove("a", ""), Some("1".into()));
+        assert_eq!(document.get("a", "DEFAULT"), None);
+    }
+
+    #[test]
+    fn bare_keys() {
+        let ini = r"[flags]
+debug
+verbose = yes";
+        assert_eq!(IniParser::new().parse(ini), Err(InnitError::MissingEquals("debug".into(), 2, 8..13)));
+
+        let parser = IniParser::new().bare_keys(true);
+        let document = parser.parse(ini).unwrap();
+        assert_eq!(document.get("debug", "flags"), Some(""));
+        assert_eq!(document.get("verbose", "flags"), Some("yes"));
+
+        let options = FormatOptions { bare_keys: true, ..Default::default() };
+        let written = document.to_string_with(&options);
+        assert!(written.split(LINE_DELIM).any(|l| l == "debug"));
+        assert_eq!(parser.parse(written).unwrap(), document);
+    }
+
+    #[test]
+    fn comments() {
+        let ini = r"# not attached
+
+# the answer
+; to everything
+answer = 42
+[section1]
+# a section comment? no, a key comment
+foo = bar
+baz = bop";
+        let mut document = IniParser::new().parse(ini).unwrap();
+        assert_eq!(document.get_comment("answer", ""), Some("the answer\nto everything"));
+        assert_eq!(document.get_comment("foo", "section1"), Some("a section comment? no, a key comment"));
+        assert_eq!(document.get_comment("baz", "section1"), None);
+
+        assert_eq!(document.set_comment("baz", "section1", "changed\nover two lines"), None);
+        assert_eq!(document.set_comment("answer", "", "just the answer"), Some("the answer\nto everything".into()));
+        let written = document.to_string();
+        assert!(written.contains(&format!("# changed{0}# over two lines{0}baz = bop", LINE_DELIM)));
+        assert!(written.contains(&format!("# just the answer{0}answer = 42", LINE_DELIM)));
+
+        let document = IniParser::new().parse(written).unwrap();
+        assert_eq!(document.get_comment("baz", "section1"), Some("changed\nover two lines"));
+        assert_eq!(document.get_comment("answer", ""), Some("just the answer"));
+    }
+
+    #[test]
+    fn bom() {
+        let ini = "foo = bar\n[section1]\nfoo = baz";
+        let with_bom = format!("\u{FEFF}{}", ini);
+        assert_eq!(IniParser::new().parse(with_bom).unwrap(), IniParser::new().parse(ini).unwrap());
+
+        let document = IniParser::new().parse("[section1]\nfoo = \u{FEFF}baz").unwrap();
+        assert_eq!(document.get("foo", "section1"), Some("\u{FEFF}baz"));
+    }
+
+    #[test]
+    fn line_endings() {
+        let lf = IniParser::new().parse("foo = bar\n[section1]\nfoo = baz\n").unwrap();
+        let crlf = IniParser::new().parse("foo = bar\r\n[section1]\r\nfoo = baz\r\n").unwrap();
+        assert_eq!(crlf.get("foo", ""), Some("bar"));
+        assert_eq!(crlf.get("foo", "section1"), Some("baz"));
+        assert_eq!(crlf, lf);
+
+        let mixed = IniParser::new().parse("foo = bar\r\n[section1]\nfoo = baz").unwrap();
+        assert_eq!(mixed, lf);
+    }
+
+    #[test]
+    fn comment_prefixes() {
+        let ini = r"// a comment
+foo = bar
+[section1]
+// another one
+foo = baz";
+        assert_eq!(IniParser::new().parse(ini), Err(InnitError::MissingEquals("// a comment".into(), 1, 0..12)));
+
+        let parser = IniParser::new().comment_prefixes(["//"]);
+        let document = parser.parse(ini).unwrap();
+        assert_eq!(document.get("foo", ""), Some("bar"));
+        assert_eq!(document.get_comment("foo", "section1"), Some("another one"));
+
+        assert_eq!(parser.parse("# not a comment"), Err(InnitError::MissingEquals("# not a comment".into(), 1, 0..15)));
+    }
+
+    #[test]
+    fn quoted_values() {
+        let ini = r#"quoted = "a=b=c"
+unquoted = a=b
+spaces = "  padded  "
+escapes = "say \"hi\"\nnew line"
+half = "open"#;
+        let document = IniParser::new().parse(ini).unwrap();
+        assert_eq!(document.get("quoted", ""), Some("\"a=b=c\""));
+
+        let document = IniParser::new().quoted_values(true).parse(ini).unwrap();
+        assert_eq!(document.get("quoted", ""), Some("a=b=c"));
+        assert_eq!(document.get("unquoted", ""), Some("a=b"));
+        assert_eq!(document.get("spaces", ""), Some("  padded  "));
+        assert_eq!(document.get("escapes", ""), Some("say \"hi\"\nnew line"));
+        assert_eq!(document.get("half", ""), Some("\"open"));
+    }
+
+    #[test]
+    fn quoted_keys() {
+        let ini = r#"[section1]
+"my key" = v
+"a=b" = c
+"items[]" = not an array
+plain = "quoted value"
+items[] = one"#;
+        let document = IniParser::new().parse(ini).unwrap();
+        assert_eq!(document.get("\"my key\"", "section1"), Some("v"));
+        assert_eq!(document.get("my key", "section1"), None);
+
+        let parser = IniParser::new().quoted_keys(true).array_keys(true);
+        let document = parser.parse(ini).unwrap();
+        assert_eq!(document.get("my key", "section1"), Some("v"));
+        assert_eq!(document.get("a=b", "section1"), Some("c"));
+        assert_eq!(document.get("items[]", "section1"), Some("not an array"));
+        assert_eq!(document.get("plain", "section1"), Some("\"quoted value\""));
+        assert_eq!(document.get_all("items", "section1"), Some(&["one".to_string()][..]));
+
+        let options = FormatOptions { quoted_keys: true, sorted: true, line_ending: LineEnding::Lf, ..Default::default() };
+        let written = document.to_string_with(&options);
+        assert_eq!(written, "[section1]\n\"a=b\" = c\n\"items[]\" = not an array\n\"my key\" = v\nplain = \"quoted value\"\nitems[] = one\n");
+        assert_eq!(parser.parse(&written).unwrap(), document);
+
+        // regenerated lines are quoted again
+        let ini = "\"a=b\" = c\nz = 1";
+        let mut document = parser.clone().preserve_formatting(true).parse(ini).unwrap();
+        document.insert("a=b", "new", "");
+        document.insert("new key", "added", "");
+        assert_eq!(document.to_string(), "\"a=b\" = new\nz = 1\n\"new key\" = added");
+        let reparsed = parser.parse(document.to_string()).unwrap();
+        assert_eq!(reparsed.get("a=b", ""), Some("new"));
+        assert_eq!(reparsed, document);
+    }
+
+    #[test]
+    fn section_escapes() {
+        let ini = r"[a\]b]
+foo = bar
+[C:\path]
+foo = baz
+[back\\slash]
+foo = bop";
+        let document = IniParser::new().parse(ini).unwrap();
+        assert_eq!(document.get("foo", "a]b"), Some("bar"));
+        assert_eq!(document.get("foo", "C:\\path"), Some("baz"));
+        assert_eq!(document.get("foo", "back\\slash"), Some("bop"));
+
+        let mut document = IniDocument::empty();
+        document.insert("foo", "bar", "a]b");
+        document.insert("foo", "baz", "C:\\new");
+        document.insert("foo", "bop", "ends with \\");
+        let written = document.to_string();
+        assert!(written.contains(r"[a\]b]"));
+        assert_eq!(IniParser::new().parse(written).unwrap(), document);
+
+        // only backslashes and closing brackets are escapes, so other backslashes are kept as they were written
+        let document = IniParser::new().parse("[C:\\new]\na = 1\n[dir\\]\nb = 2\n[tab\\t\\r]\nc = 3").unwrap();
+        assert_eq!(document.get("a", "C:\\new"), Some("1"));
+        assert_eq!(document.get("b", "dir\\"), Some("2"));
+        assert_eq!(document.get("c", "tab\\t\\r"), Some("3"));
+    }
+
+    #[test]
+    fn trimming() {
+        let ini = "  a  =  b  ";
+        for (trim_keys, trim_values, key, value) in [(true, true, "a", "b"), (false, true, "  a  ", "b"), (true, false, "a", "  b  "), (false, false, "  a  ", "  b  ")] {
+            let document = IniParser::new().trim_keys(trim_keys).trim_values(trim_values).parse(ini).unwrap();
+            assert_eq!(document.get_section("").unwrap().iter().collect::<Vec<_>>(), [(key, value)]);
+        }
+
+        let ini = "[ section1 ]\nfoo = bar\n[\tsection2\\ ]\nfoo = baz";
+        let document = IniParser::new().parse(ini).unwrap();
+        assert_eq!(document.get("foo", "section1"), Some("bar"));
+        // a backslash escapes the whitespace after it, so it isn't trimmed
+        assert_eq!(document.get("foo", "section2 "), Some("baz"));
+        let document = IniParser::new().trim_section_names(false).parse(ini).unwrap();
+        assert_eq!(document.get("foo", "section1"), None);
+        assert_eq!(document.get("foo", " section1 "), Some("bar"));
+        assert_eq!(document.get("foo", "\tsection2 "), Some("baz"));
+        // but not after an escaped backslash
+        let document = IniParser::new().parse("[ dir\\\\ ]\nfoo = bar").unwrap();
+        assert_eq!(document.get("foo", "dir\\"), Some("bar"));
+        for parser in [IniParser::new(), IniParser::new().trim_section_names(false)] {
+            assert!(matches!(parser.parse("[  ]\nfoo = bar"), Err(InnitError::EmptyStringSection(..))));
+        }
+
+        let parser = IniParser::new().trim_values(false).quoted_values(true);
+        assert_eq!(parser.parse("a =\"  b  \"").unwrap().get("a", ""), Some("  b  "));
+        assert_eq!(parser.parse("a = \"b\"").unwrap().get("a", ""), Some(" \"b\""));
+    }
+
+    #[test]
+    fn parse_onto() {
+        let base = "foo = bar\n[section1]\n# the key\nkey = old\nother = kept";
+        let mut document = IniDocument::from_string(base).unwrap();
+        document.apply_string("[section1]\nkey = new\n[section2]\nbaz = bop").unwrap();
+        assert_eq!(document.get("key", "section1"), Some("new"));
+        assert_eq!(document.get_comment("key", "section1"), None);
+        assert_eq!(document.get("other", "section1"), Some("kept"));
+        assert_eq!(document.get("baz", "section2"), Some("bop"));
+        assert_eq!(document.get("foo", ""), Some("bar"));
+
+        let before = document.clone();
+        let err = document.apply_string("[section1]\nkey = newer\nbeans").unwrap_err();
+        assert_eq!(err.line(), Some(3));
+        assert_eq!(document, before);
+
+        IniParser::new().array_keys(true).parse_onto(&mut document, "items[] = a\nitems[] = b").unwrap();
+        assert_eq!(document.get_all("items", "").map(|a| a.len()), Some(2));
+
+        let parser = IniParser::new().merge_strategy(MergeStrategy::KeepExisting);
+        parser.parse_onto(&mut document, "foo = changed\n[section1]\n# ignored\nkey = newer\nadded = yes").unwrap();
+        assert_eq!(document.get("foo", ""), Some("bar"));
+        assert_eq!(document.get("key", "section1"), Some("new"));
+        assert_eq!(document.get_comment("key", "section1"), None);
+        assert_eq!(document.get("added", "section1"), Some("yes"));
+    }
+
+    #[test]
+    fn delimiters() {
+        let ini = "a = 1\nb: 2\nc: d = 3\nurl = http://example.com";
+        let document = IniParser::new().delimiters(['=', ':']).parse(ini).unwrap();
+        assert_eq!(document.get("a", ""), Some("1"));
+        assert_eq!(document.get("b", ""), Some("2"));
+        assert_eq!(document.get("c", ""), Some("d = 3"));
+        assert_eq!(document.get("url", ""), Some("http://example.com"));
+
+        assert_eq!(IniParser::new().parse(ini), Err(InnitError::MissingEquals("b: 2".into(), 2, 6..10)));
+        let document = IniParser::new().delimiters([]).parse("a = 1").unwrap();
+        assert_eq!(document.get("a", ""), Some("1"));
+        let document = IniParser::new().delimiters([':']).quoted_values(true).parse("\"a:b\": \"c\"").unwrap();
+        assert_eq!(document.get("\"a:b\"", ""), Some("c"));
+    }
+
+    #[test]
+    fn warnings() {
+        let ini = "foo = bar\n[section1]\nfoo = baz\nqux = one\nqux = two\nitems[] = a\nitems[] = b";
+        let (document, warnings) = IniDocument::from_string_with_warnings(ini).unwrap();
+        assert_eq!(document.get("qux", "section1"), Some("two"));
+        assert_eq!(warnings, [
+            Warning::DuplicateKey { section: "section1".into(), key: "qux".into(), line: 5 },
+            Warning::DuplicateKey { section: "section1".into(), key: "items[]".into(), line: 7 },
+        ]);
+        assert_eq!(warnings[0].to_string(), "duplicate key `qux` in section `section1` on line 5");
+
+        // array values add to each other instead of replacing
+        let (_, warnings) = IniParser::new().array_keys(true).parse_with_warnings(ini).unwrap();
+        assert_eq!(warnings.len(), 1);
+        assert!(IniDocument::from_string_with_warnings("foo = bar\nbeans").is_err());
+    }
+
+    #[test]
+    fn spans() {
+        let ini = "foo = bar\r\n[section1]\r\n  beans  \r\nbaz = bop";
+        let err = IniParser::new().parse(ini).unwrap_err();
+        assert_eq!(err, InnitError::MissingEquals("beans".into(), 3, 23..32));
+        assert_eq!(&ini[err.span().unwrap()], "  beans  ");
+        assert_eq!(err.line(), Some(3));
+
+        let ini = "\u{FEFF}foo = bar\n[]\n";
+        let err = IniParser::new().parse(ini).unwrap_err();
+        assert_eq!(&ini[err.span().unwrap()], "[]");
+
+        let ini = "# comment\nfoo = bar";
+        let err = IniParser::new().require_sections(true).parse(ini).unwrap_err();
+        assert_eq!(&ini[err.span().unwrap()], "foo = bar");
+
+        assert_eq!(InnitError::EmptySectionName.span(), None);
+        assert_eq!(InnitError::EmptySectionName.line(), None);
+    }
+
+    /// every parser option that changes how lines are read
+    fn parsers() -> Vec<IniParser> {
+        vec![
+            IniParser::new(),
+            IniParser::new().trim_keys(false),
+            IniParser::new()
+                .bare_keys(true)
+                .quoted_values(true)
+                .array_keys(true)
+                .preserve_formatting(true)
+                .inline_comments(true)
+                .trim_values(false)
+                .empty_section(EmptySectionPolicy::Global)
+                .comment_prefixes(["//", "#"])
+                .delimiters(['=', ':']),
+        ]
+    }
+
+    /// characters that mean something to the parser, so random lines built from them hit the edge cases
+    const AWKWARD: &[char] = &['[', ']', '=', '\\', '"', '#', ';', '/', ' ', '\t', '\n', '\r', 'a', 'é', '\u{FEFF}', '\u{301}', '\u{3000}'];
+
+    fn no_panic(s: &str) -> bool {
+        for parser in parsers() {
+            if let Ok(document) = parser.parse(s) {
+                document.to_string();
+                document.to_string_sorted();
+            }
+        }
+        true
+    }
+
+    #[test]
+    fn never_panics() {
+        fn arbitrary(s: String) -> bool {
+            no_panic(&s)
+        }
+        fn awkward(picks: Vec<u8>) -> bool {
+            let s: String = picks.iter().map(|&i| AWKWARD[i as usize % AWKWARD.len()]).collect();
+            no_panic(&s)
+        }
+        quickcheck::quickcheck(arbitrary as fn(String) -> bool);
+        quickcheck::quickcheck(awkward as fn(Vec<u8>) -> bool);
+    }
+
+    #[test]
+    fn preserved_round_trip() {
+        fn preserved(picks: Vec<u8>) -> bool {
+            let s: String = picks.iter().map(|&i| AWKWARD[i as usize % AWKWARD.len()]).collect();
+            parsers().into_iter().map(|p| p.preserve_formatting(true)).all(|parser| {
+                match parser.parse(&s) {
+                    Ok(document) => document.to_string() == s,
+                    Err(_) => true
+                }
+            })
+        }
+        quickcheck::quickcheck(preserved as fn(Vec<u8>) -> bool);
+    }
+
+    #[test]
+    fn comments_round_trip() {
+        fn tidied(picks: Vec<u8>) -> bool {
+            // a key starting with a byte order mark can't be written first, the same as in round_trip, so they're only allowed at the start
+            let s: String = picks.iter().map(|&i| AWKWARD[i as usize % AWKWARD.len()])
+                .enumerate().filter(|&(n, c)| n == 0 || c != '\u{FEFF}').map(|(_, c)| c)
+                .collect();
+            let parser = IniParser::new().empty_section(EmptySectionPolicy::Global).bare_keys(true);
+            match parser.clone().preserve_comments(true).parse(&s) {
+                Ok(document) => parser.parse(document.to_string()).as_ref() == Ok(&document),
+                Err(_) => true
+            }
+        }
+        quickcheck::quickcheck(tidied as fn(Vec<u8>) -> bool);
+    }
+
+    #[test]
+    fn round_trip() {
+        /// make a string into a key or value that can be written out and parsed back the same
+        fn clean(s: &str, key: bool) -> String {
+            let s: String = s.chars().filter(|&c| !(matches!(c, '\n' | '\r') || key && matches!(c, '=' | '\u{FEFF}'))).collect();
+            if key {
+                // keys can't look like comments or section headers
+                s.trim_start_matches(|c: char| c.is_whitespace() || matches!(c, '#' | ';' | '[')).trim_end().into()
+            }
+            else {
+                s.trim().into()
+            }
+        }
+        fn round_trip(entries: Vec<(String, String, String)>) -> bool {
+            let mut document = IniDocument::empty();
+            for (k, v, section) in entries {
+                let k = clean(&k, true);
+                // section names with line breaks can't be written, see `IniDocument::try_insert_validated`
+                if k.is_empty() || section.contains(['\n', '\r']) {
+                    continue
+                }
+                document.insert(k, clean(&v, false), section);
+            }
+            IniDocument::from_string(document.to_string()) == Ok(document)
+        }
+        quickcheck::quickcheck(round_trip as fn(Vec<(String, String, String)>) -> bool);
+    }
+
+    #[test]
+    fn malformed_sections() {
+        let err = IniParser::new().parse("foo = bar\n[abc\nbaz = bop").unwrap_err();
+        assert_eq!(err, InnitError::MalformedSection { text: "[abc".into(), line: 2, span: 10..14 });
+        assert_eq!(err.to_string(), "malformed section header `[abc` on line 2");
+        let err = IniParser::new().bare_keys(true).parse("abc]").unwrap_err();
+        assert_eq!(err, InnitError::MalformedSection { text: "abc]".into(), line: 1, span: 0..4 });
+
+        // values can still have brackets in them
+        let document = IniParser::new().parse("list = [1, 2]\n[x = y").unwrap();
+        assert_eq!(document.get("list", ""), Some("[1, 2]"));
+        assert_eq!(document.get("[x", ""), Some("y"));
+    }
+
+    #[test]
+    fn header_comments() {
+        let ini = "[server] ; production settings\nhost = example.com\n[a\\]b]# escaped\nfoo = bar\n[plain]\nbaz = bop\n";
+        let err = IniParser::new().parse(ini).unwrap_err();
+        assert_eq!(err, InnitError::MalformedSection { text: "[server] ; production settings".into(), line: 1, span: 0..30 });
+
+        let parser = IniParser::new().inline_comments(true);
+        let document = parser.parse(ini).unwrap();
+        assert_eq!(document.get("host", "server"), Some("example.com"));
+        assert_eq!(document.get("foo", "a]b"), Some("bar"));
+        assert_eq!(document.get("baz", "plain"), Some("bop"));
+        assert!(parser.parse("[server] production").is_err());
+
+        let document = parser.preserve_formatting(true).parse(ini).unwrap();
+        assert_eq!(document.to_string(), ini);
+    }
+
+    #[test]
+    fn bracket_lines() {
+        for line in ["[", "]", "[[", "]]", "[\\"] {
+            assert_eq!(IniParser::new().parse(line), Err(InnitError::MalformedSection { text: line.into(), line: 1, span: 0..line.len() }));
+        }
+        assert_eq!(IniParser::new().parse("]["), Err(InnitError::MissingEquals("][".into(), 1, 0..2)));
+        let document = IniParser::new().parse("[]]\nfoo = bar").unwrap();
+        assert_eq!(document.get("foo", "]"), Some("bar"));
+    }
+
+}
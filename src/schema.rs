@@ -0,0 +1,162 @@
+//! Checking documents against a schema of expected keys.
+
+use super::*;
+
+/// The kind of value a key in a [`Schema`] is expected to hold.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FieldType {
+    /// Any string, which every value is.
+    String,
+    /// A whole number that fits in an `i64`, eg. `-42`.
+    Int,
+    /// A number that parses as an `f64`, eg. `3.14`.
+    Float,
+    /// One of `true`, `false`, `yes`, `no`, `on`, `off`, `1` or `0`, in any case.
+    Bool,
+}
+impl FieldType {
+    /// Check if a value is of this type.
+    pub fn matches(&self, value: &str) -> bool {
+        match self {
+            FieldType::String => true,
+            FieldType::Int => value.parse::<i64>().is_ok(),
+            FieldType::Float => value.parse::<f64>().is_ok(),
+            FieldType::Bool => ["true", "false", "yes", "no", "on", "off", "1", "0"].iter().any(|b| b.eq_ignore_ascii_case(value)),
+        }
+    }
+}
+impl fmt::Display for FieldType {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            FieldType::String => "string",
+            FieldType::Int => "integer",
+            FieldType::Float => "float",
+            FieldType::Bool => "boolean",
+        })
+    }
+}
+
+/// A set of keys a document is expected to have, and the types of their values, for use with [`IniDocument::validate`].
+/// 
+/// Keys are added with builder-style methods:
+/// 
+/// ```
+/// # use innit::*;
+/// let schema = Schema::new()
+///     .require("port", "server", FieldType::Int)
+///     .optional("debug", "server", FieldType::Bool);
+/// let document = IniDocument::from_string("[server]\nport = 8080").unwrap();
+/// assert!(document.validate(&schema).is_ok());
+/// ```
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct Schema {
+    fields: Vec<Field>,
+}
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct Field {
+    key: String,
+    section: String,
+    ty: FieldType,
+    required: bool,
+}
+impl Schema {
+    /// Create a new empty schema, which every document passes.
+    pub fn new() -> Schema {
+        Schema::default()
+    }
+    /// Require a key to exist in a given section, with a value of the given type.
+    pub fn require<T: Into<String>, U: Into<String>>(self, key: T, section: U, ty: FieldType) -> Schema {
+        self.field(key, section, ty, true)
+    }
+    /// Allow a key to be missing from a given section, but if it's there, require its value to be of the given type.
+    pub fn optional<T: Into<String>, U: Into<String>>(self, key: T, section: U, ty: FieldType) -> Schema {
+        self.field(key, section, ty, false)
+    }
+
+    fn field<T: Into<String>, U: Into<String>>(mut self, key: T, section: U, ty: FieldType, required: bool) -> Schema {
+        self.fields.push(Field { key: key.into(), section: section.into(), ty, required });
+        self
+    }
+}
+
+/// A way a document failed to match a [`Schema`], as found by [`IniDocument::validate`].
+#[derive(Debug, Error, Clone, PartialEq, Eq)]
+pub enum ValidationError {
+    /// A required key doesn't exist.
+    #[error("missing key `{key}` in section `{section}`")]
+    Missing {
+        /// The section the key should be in.
+        section: String,
+        /// The key.
+        key: String,
+    },
+    /// A key exists, but its value isn't of the expected type.
+    #[error("key `{key}` in section `{section}` should be {expected}, but is `{value}`")]
+    WrongType {
+        /// The section the key is in.
+        section: String,
+        /// The key.
+        key: String,
+        /// The type the schema expects.
+        expected: FieldType,
+        /// The value in the document.
+        value: String,
+    },
+}
+
+impl IniDocument {
+    /// Check the document against a schema. Keys that aren't in the schema are allowed.
+    /// 
+    /// Returns every problem found, in the order the keys were added to the schema, rather than stopping at the first one.
+    pub fn validate(&self, schema: &Schema) -> Result<(), Vec<ValidationError>> {
+        let mut errors = Vec::new();
+        for field in &schema.fields {
+            match self.get(&field.key, &field.section) {
+                None if field.required => errors.push(ValidationError::Missing { section: field.section.clone(), key: field.key.clone() }),
+                Some(v) if !field.ty.matches(v) => errors.push(ValidationError::WrongType {
+                    section: field.section.clone(),
+                    key: field.key.clone(),
+                    expected: field.ty,
+                    value: v.into(),
+                }),
+                _ => ()
+            }
+        }
+        if errors.is_empty() {
+            Ok(())
+        }
+        else {
+            Err(errors)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn validate() {
+        let schema = Schema::new()
+            .require("host", "server", FieldType::String)
+            .require("port", "server", FieldType::Int)
+            .optional("debug", "", FieldType::Bool)
+            .optional("ratio", "", FieldType::Float);
+
+        let document = IniDocument::from_string("debug = Yes\n[server]\nhost = localhost\nport = 8080\nextra = ignored").unwrap();
+        assert_eq!(document.validate(&schema), Ok(()));
+
+        let document = IniDocument::from_string("[server]\nport = 8080").unwrap();
+        assert_eq!(document.validate(&schema), Err(vec![
+            ValidationError::Missing { section: "server".into(), key: "host".into() },
+        ]));
+
+        let document = IniDocument::from_string("debug = maybe\nratio = 0.5\n[server]\nhost = localhost\nport = eighty").unwrap();
+        let errors = document.validate(&schema).unwrap_err();
+        assert_eq!(errors, vec![
+            ValidationError::WrongType { section: "server".into(), key: "port".into(), expected: FieldType::Int, value: "eighty".into() },
+            ValidationError::WrongType { section: "".into(), key: "debug".into(), expected: FieldType::Bool, value: "maybe".into() },
+        ]);
+        assert_eq!(errors[0].to_string(), "key `port` in section `server` should be integer, but is `eighty`");
+    }
+}
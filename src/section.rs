@@ -0,0 +1,279 @@
+//! The keys and values of a single section.
+
+use super::*;
+
+/// The keys and values of a single document section, as returned by [`IniDocument::get_section`].
+/// 
+/// This doesn't expose the map the section is stored in, so the storage can change without breaking code that reads sections.
+/// If you really need the map, use [`as_map`](Section::as_map).
+/// 
+/// ```
+/// # use innit::*;
+/// let document = IniDocument::from_string("[section1]\nfoo = bar").unwrap();
+/// let section = document.get_section("section1").unwrap();
+/// assert_eq!(section.get("foo"), Some("bar"));
+/// assert_eq!(section.len(), 1);
+/// ```
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct Section(pub(crate) SectionMap);
+impl Section {
+    /// Create a new empty section with room for at least `keys` keys before it has to reallocate.
+    /// With the `sorted` feature, this is the same as [`Section::default`].
+    /// 
+    /// Sections built up on their own can be put into a document with [`IniDocument::insert_section`]:
+    /// 
+    /// ```
+    /// # use innit::*;
+    /// let mut section = Section::with_capacity(2);
+    /// section.extend([("foo", "bar"), ("baz", "bop")]);
+    /// let mut document = IniDocument::empty();
+    /// document.insert_section("section1", section.into_map());
+    /// assert_eq!(document.get("baz", "section1"), Some("bop"));
+    /// ```
+    pub fn with_capacity(keys: usize) -> Section {
+        Section(map_with_capacity(keys))
+    }
+    /// Get a reference to the value of a key.
+    pub fn get<T: AsRef<str>>(&self, key: T) -> Option<&str> {
+        self.0.get(key.as_ref()).map(|v| v.as_str())
+    }
+    /// Determine if the section contains a key.
+    pub fn contains_key<T: AsRef<str>>(&self, key: T) -> bool {
+        self.0.contains_key(key.as_ref())
+    }
+    /// Get the number of key/value pairs in the section.
+    pub fn len(&self) -> usize {
+        self.0.len()
+    }
+    /// Determine if the section has no keys.
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+    /// Iterate over the keys and values in the section, in the order they're stored in.
+    pub fn iter(&self) -> impl Iterator<Item = (&str, &str)> {
+        self.0.iter().map(|(k, v)| (k.as_str(), v.as_str()))
+    }
+    /// Iterate over the keys in the section, in the order they're stored in.
+    pub fn keys(&self) -> impl Iterator<Item = &str> {
+        self.0.keys().map(|k| k.as_str())
+    }
+    /// Iterate over the values in the section, in the order they're stored in.
+    pub fn values(&self) -> impl Iterator<Item = &str> {
+        self.0.values().map(|v| v.as_str())
+    }
+    /// Get the map the section is stored in. This is a [`HashMap`], unless the `sorted` feature is enabled.
+    pub fn as_map(&self) -> &SectionMap {
+        &self.0
+    }
+    /// Turn the section into the map it's stored in.
+    pub fn into_map(self) -> SectionMap {
+        self.0
+    }
+}
+/// Add key/value pairs to a section that isn't part of a document, replacing the values of keys that are already there.
+impl<K: Into<String>, V: Into<String>> Extend<(K, V)> for Section {
+    fn extend<I: IntoIterator<Item = (K, V)>>(&mut self, iter: I) {
+        self.0.extend(iter.into_iter().map(|(k, v)| (k.into(), v.into())))
+    }
+}
+impl From<SectionMap> for Section {
+    fn from(map: SectionMap) -> Section {
+        Section(map)
+    }
+}
+
+/// A handle for changing a single section of a document, as returned by [`IniDocument::section_mut`],
+/// so the section name doesn't have to be passed on every call.
+/// 
+/// Changes go through the document's own methods, so comments and everything else are kept in sync the same way.
+/// For read-only access to a section, use [`IniDocument::section`] or [`IniDocument::get_section`].
+/// 
+/// ```
+/// # use innit::*;
+/// let mut document = IniDocument::empty();
+/// let mut server = document.section_mut("server");
+/// server.insert("host", "localhost");
+/// server.insert("port", "8080");
+/// assert_eq!(server.get("port"), Some("8080"));
+/// assert_eq!(document.get("host", "server"), Some("localhost"));
+/// ```
+#[derive(Debug)]
+pub struct SectionHandle<'a> {
+    document: &'a mut IniDocument,
+    name: String,
+}
+impl SectionHandle<'_> {
+    /// Get the name of the section, which is the empty string for the unnamed section.
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+    /// Get a reference to the value of a key.
+    pub fn get<T: AsRef<str>>(&self, key: T) -> Option<&str> {
+        self.document.get(key.as_ref(), &self.name)
+    }
+    /// Insert a key into the section. Returns the old value if it exists.
+    pub fn insert<T: Into<String>, U: Into<String>>(&mut self, key: T, value: U) -> Option<String> {
+        self.document.insert(key, value, self.name.as_str())
+    }
+    /// Remove a key from the section. Returns the value, if it existed.
+    pub fn remove<T: AsRef<str>>(&mut self, key: T) -> Option<String> {
+        self.document.remove(key.as_ref(), &self.name)
+    }
+    /// Iterate over the keys and values in the section, in the order they're stored in.
+    pub fn iter(&self) -> impl Iterator<Item = (&str, &str)> {
+        self.document.sections[&self.name].iter()
+    }
+}
+
+/// A read-only handle for a single section of a document, as returned by [`IniDocument::section`],
+/// so the section name doesn't have to be passed on every call.
+/// 
+/// Unlike the [`Section`] from [`IniDocument::get_section`], this knows its own name, and can get the comments above keys.
+/// 
+/// ```
+/// # use innit::*;
+/// let document = IniDocument::from_string("[server]\n# where to listen\nport = 8080").unwrap();
+/// let server = document.section("server").unwrap();
+/// assert_eq!(server.get("port"), Some("8080"));
+/// assert_eq!(server.get_comment("port"), Some("where to listen"));
+/// ```
+#[derive(Debug, Clone, Copy)]
+pub struct SectionView<'a> {
+    document: &'a IniDocument,
+    name: &'a str,
+}
+impl<'a> SectionView<'a> {
+    /// Get the name of the section, which is the empty string for the unnamed section.
+    pub fn name(&self) -> &'a str {
+        self.name
+    }
+    /// Get a reference to the value of a key.
+    pub fn get<T: AsRef<str>>(&self, key: T) -> Option<&'a str> {
+        self.document.get(key.as_ref(), self.name)
+    }
+    /// Get the comment above a key, if it has one.
+    pub fn get_comment<T: AsRef<str>>(&self, key: T) -> Option<&'a str> {
+        self.document.get_comment(key.as_ref(), self.name)
+    }
+    /// Iterate over the keys and values in the section, in the order they're stored in.
+    pub fn iter(&self) -> impl Iterator<Item = (&'a str, &'a str)> {
+        self.document.sections[self.name].iter()
+    }
+}
+
+impl IniDocument {
+    /// Get a read-only handle for a single section, or `None` if the section doesn't exist.
+    /// The empty string gets a handle for the unnamed section, if it has been created.
+    pub fn section<T: AsRef<str>>(&self, name: T) -> Option<SectionView<'_>> {
+        let (name, _) = self.sections.get_key_value(name.as_ref())?;
+        Some(SectionView { document: self, name })
+    }
+    /// Get a handle for changing a single section, creating the section if it doesn't exist.
+    /// The empty string gets a handle for the unnamed section.
+    pub fn section_mut<T: Into<String>>(&mut self, name: T) -> SectionHandle<'_> {
+        let name = self.stored_section(name.into());
+        if !self.sections.contains_key(&name) {
+            self.sections.insert(name.clone(), Section::default());
+            self.index_section_changed(&name)
+        }
+        SectionHandle { document: self, name }
+    }
+    /// Get a handle for changing a single section, or `None` if the section doesn't exist.
+    /// 
+    /// This is a handle rather than a mutable reference to the [`Section`], so that comments and anything else kept alongside keys stay in sync.
+    pub fn get_section_mut<T: AsRef<str>>(&mut self, section: T) -> Option<SectionHandle<'_>> {
+        let name = self.sections.get_key_value(section.as_ref())?.0.clone();
+        Some(SectionHandle { document: self, name })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn accessors() {
+        let document = IniDocument::from_string("[section1]\nfoo = bar\nbaz = bop").unwrap();
+        let section = document.get_section("section1").unwrap();
+        assert_eq!(section.get("foo"), Some("bar"));
+        assert_eq!(section.get("qux"), None);
+        assert!(section.contains_key("baz"));
+        assert_eq!(section.len(), 2);
+        assert!(!section.is_empty());
+
+        let mut pairs: Vec<(&str, &str)> = section.iter().collect();
+        pairs.sort();
+        assert_eq!(pairs, [("baz", "bop"), ("foo", "bar")]);
+        let mut keys: Vec<&str> = section.keys().collect();
+        keys.sort();
+        assert_eq!(keys, ["baz", "foo"]);
+        let mut values: Vec<&str> = section.values().collect();
+        values.sort();
+        assert_eq!(values, ["bar", "bop"]);
+
+        assert_eq!(section.as_map().get("foo").map(|v| v.as_str()), Some("bar"));
+        assert_eq!(Section::from(section.clone().into_map()), *section);
+        assert!(Section::default().is_empty());
+    }
+
+    #[test]
+    fn handle() {
+        let mut document = IniDocument::from_string("foo = bar\n[section1]\nbaz = bop").unwrap();
+        let mut section = document.section_mut("section1");
+        assert_eq!(section.name(), "section1");
+        assert_eq!(section.insert("qux", "quux"), None);
+        assert_eq!(section.insert("baz", "new"), Some("bop".into()));
+        assert_eq!(section.get("baz"), Some("new"));
+        assert_eq!(section.remove("qux"), Some("quux".into()));
+        assert_eq!(section.get("qux"), None);
+        assert_eq!(section.iter().collect::<Vec<_>>(), [("baz", "new")]);
+
+        let mut global = document.section_mut("");
+        assert_eq!(global.get("foo"), Some("bar"));
+        global.insert("foo", "changed");
+        assert_eq!(document.get("foo", ""), Some("changed"));
+
+        document.section_mut("section2");
+        assert_eq!(document.key_count("section2"), Some(0));
+    }
+
+    #[test]
+    fn view() {
+        let document = IniDocument::from_string("foo = bar\n[section1]\n# about baz\nbaz = bop\nqux = quux").unwrap();
+        assert!(document.section("section2").is_none());
+
+        let section = document.section("section1").unwrap();
+        assert_eq!(section.name(), "section1");
+        assert_eq!(section.get("baz"), Some("bop"));
+        assert_eq!(section.get("qux"), Some("quux"));
+        assert_eq!(section.get("foo"), None);
+        assert_eq!(section.get_comment("baz"), Some("about baz"));
+        assert_eq!(section.get_comment("qux"), None);
+        let mut pairs: Vec<(&str, &str)> = section.iter().collect();
+        pairs.sort();
+        assert_eq!(pairs, [("baz", "bop"), ("qux", "quux")]);
+
+        let global = document.section("").unwrap();
+        assert_eq!(global.name(), "");
+        assert_eq!(global.get("foo"), Some("bar"));
+        assert!(IniDocument::empty().section("").is_none());
+    }
+
+    #[test]
+    fn get_section_mut() {
+        let mut document = IniDocument::from_string("[section1]\n# comment\na = 1\nb = 2\nc = 3").unwrap();
+        assert!(document.get_section_mut("section2").is_none());
+        assert!(document.get_section("section2").is_none());
+
+        let mut section = document.get_section_mut("section1").unwrap();
+        for key in ["a", "b", "c"] {
+            let doubled = section.get(key).unwrap().parse::<i32>().unwrap() * 2;
+            section.insert(key, doubled.to_string());
+        }
+        section.remove("a");
+        assert_eq!(document.get("b", "section1"), Some("4"));
+        assert_eq!(document.get("c", "section1"), Some("6"));
+        assert_eq!(document.get_comment("a", "section1"), None);
+        assert_eq!(document.key_count("section1"), Some(2));
+    }
+}
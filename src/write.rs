@@ -0,0 +1,805 @@
+//! Turning documents back into strings.
+
+use super::*;
+use std::borrow::Cow;
+use std::cmp::Ordering;
+use std::fmt::Write as _;
+use std::io;
+
+impl IniDocument {
+    /// Turn a document back into its string representation, using the given [`FormatOptions`].
+    /// 
+    /// Ordering of sections, keys and values is not preserved, due to limitations of Rust's hashmap struct.
+    /// The original formatting kept by [`IniParser::preserve_formatting`] is not used either, see [`to_string`](ToString::to_string) for that.
+    /// With the `sorted` feature, they're always written in sorted order instead.
+    pub fn to_string_with(&self, options: &FormatOptions) -> String {
+        self.write_ordered(options, options.section_order.as_deref(), options.key_order.as_deref())
+    }
+    /// write with the comparators passed in, rather than the ones in the options, so they don't have to be boxed
+    fn write_ordered(&self, options: &FormatOptions, section_order: Option<&BorrowedComparator<'_>>, key_order: Option<&BorrowedComparator<'_>>) -> String {
+        let mut blocks: Vec<(&String, &Section)> = Vec::new();
+        let global_name = self.global_section.as_str();
+        // a section stored under the empty string can't be written with a header, so it goes with the global section
+        let names: &[&str] = if global_name == "" { &[""] } else { &[global_name, ""] };
+        let global: Vec<(&String, &Section)> = names.iter()
+            .filter_map(|name| self.sections.get_key_value(*name))
+            .filter(|(name, data)| !data.is_empty() || self.section_arrays(name).is_some())
+            .collect();
+        let headerless = |name: &str| name == global_name || name == "";
+
+        if let Some(cmp) = section_order {
+            // the unnamed section is ordered along with the others
+            blocks.extend(global);
+            blocks.extend(self.sections.iter().filter(|(k, _)| !headerless(k)));
+            // the sort is stable, so the two headerless blocks stay together
+            blocks.sort_by(|(a, _), (b, _)| cmp(self.lookup_section(a), self.lookup_section(b)))
+        }
+        else {
+            if options.global_position == GlobalPosition::First {
+                blocks.extend(global.iter().copied())
+            }
+            let named = blocks.len();
+            blocks.extend(self.sections.iter().filter(|(k, _)| !headerless(k)));
+            if options.sorted {
+                blocks[named..].sort_by_key(|(k, _)| *k)
+            }
+            if options.global_position == GlobalPosition::Last {
+                blocks.extend(global)
+            }
+        }
+
+        let nl = options.line_ending.as_str();
+        let mut ret = String::new();
+        for (i, (k, v)) in blocks.into_iter().enumerate() {
+            if i != 0 && options.blank_line_between_sections {
+                ret.push_str(nl)
+            }
+            if !headerless(k) {
+                ret.push_str(&format!("[{}]{}", escape_section_name(k), nl));
+            }
+            let _ = write_section(&mut ret, k, v, self.meta.get(k), self.section_arrays(k), options, key_order);
+        }
+
+        ret
+    }
+    /// Turn a document back into its string representation, like [`to_string_with`](IniDocument::to_string_with),
+    /// but fail if there's a value with a line break in it that can't be written without corrupting the document.
+    /// 
+    /// Those values can only be written with [`FormatOptions::quoted_values`] on, and the document parsed back with [`IniParser::quoted_values`].
+    /// 
+    /// ```
+    /// # use innit::*;
+    /// let mut document = IniDocument::empty();
+    /// document.insert("foo", "two\nlines", "");
+    /// assert_eq!(document.try_to_string_with(&FormatOptions::default()), Err(InnitError::LineBreakInValue { key: "foo".into(), section: "".into() }));
+    /// let written = document.try_to_string_with(&FormatOptions { quoted_values: true, ..Default::default() }).unwrap();
+    /// assert_eq!(IniParser::new().quoted_values(true).parse(written).unwrap(), document);
+    /// ```
+    pub fn try_to_string_with(&self, options: &FormatOptions) -> Result<String, InnitError> {
+        if !options.quoted_values {
+            if let Some((section, key)) = self.value_with_line_break(&['\n', '\r']) {
+                return Err(InnitError::LineBreakInValue { key: key.clone(), section: section.clone() })
+            }
+        }
+        Ok(self.to_string_with(options))
+    }
+    /// the section and key of a value with any of the line break characters in it, if there are any
+    pub(crate) fn value_with_line_break(&self, breaks: &[char]) -> Option<(&String, &String)> {
+        let arrays = self.arrays.iter().flat_map(|(s, a)| a.iter().flat_map(move |(k, values)| values.iter().map(move |v| (s, k, v))));
+        let values = self.sections.iter().flat_map(|(s, data)| data.0.iter().map(move |(k, v)| (s, k, v)));
+        values.chain(arrays).find(|(_, _, v)| v.contains(breaks)).map(|(s, k, _)| (s, k))
+    }
+    /// Write a single section to a writer, header and all, without building the whole document as a string first.
+    /// The global section is written without a header. Keys and values are quoted the same way as [`to_string`](ToString::to_string)
+    /// would quote them, and everything else uses the default [`FormatOptions`], see [`write_section_to_with`](IniDocument::write_section_to_with) to choose them.
+    /// 
+    /// The original text kept by [`IniParser::preserve_formatting`] and [`IniParser::preserve_comments`] isn't followed,
+    /// so a section from a document parsed with either is written the same as it would be without them,
+    /// which can differ from the same section in [`to_string`](ToString::to_string).
+    /// 
+    /// Fails with [`io::ErrorKind::NotFound`] if the section doesn't exist, or with whatever error the writer returns.
+    pub fn write_section_to<T: AsRef<str>, W: io::Write>(&self, section: T, w: W) -> io::Result<()> {
+        self.write_section_to_with(section, w, &self.display_options())
+    }
+    /// Write a single section to a writer, like [`write_section_to`](IniDocument::write_section_to), using the given [`FormatOptions`].
+    /// 
+    /// ```
+    /// # use innit::*;
+    /// let document = IniDocument::from_string("foo = bar\n[section1]\nfoo = baz").unwrap();
+    /// let mut out = Vec::new();
+    /// let options = FormatOptions { line_ending: LineEnding::Lf, ..Default::default() };
+    /// document.write_section_to_with("section1", &mut out, &options).unwrap();
+    /// assert_eq!(out, b"[section1]\nfoo = baz\n");
+    /// ```
+    pub fn write_section_to_with<T: AsRef<str>, W: io::Write>(&self, section: T, w: W, options: &FormatOptions) -> io::Result<()> {
+        let (name, data) = self.sections.get_key_value(section.as_ref())
+            .ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, InnitError::NoSuchSection(section.as_ref().into())))?;
+        let mut out = IoAdapter { inner: w, error: None };
+        let written = if *name != self.global_section && name != "" {
+            write!(out, "[{}]{}", escape_section_name(name), options.line_ending.as_str())
+        }
+        else {
+            Ok(())
+        };
+        match written.and_then(|_| write_section(&mut out, name, data, self.meta.get(name), self.section_arrays(name), options, options.key_order.as_deref())) {
+            Ok(()) => Ok(()),
+            Err(_) => Err(out.error.unwrap_or_else(|| io::Error::other("formatting failed")))
+        }
+    }
+    /// Turn a document back into a string with sections and keys in sorted order, and the unnamed section first.
+    /// 
+    /// Unlike [`to_string`](ToString::to_string), this gives the same output every time for the same document.
+    pub fn to_string_sorted(&self) -> String {
+        self.to_string_with(&FormatOptions { sorted: true, ..Default::default() })
+    }
+    /// Turn a document into its canonical string, so that documents with the same contents are always written the same,
+    /// which is useful for keeping them in version control.
+    /// 
+    /// Sections and keys are sorted, with the unnamed section first, every key is written as `key = value`, and lines end with `\n`,
+    /// even with the `crlf` feature. Every line, including the last, ends with a line ending.
+    pub fn to_canonical_string(&self) -> String {
+        self.to_string_with(&FormatOptions { sorted: true, line_ending: LineEnding::Lf, ..Default::default() })
+    }
+    /// Turn a document back into a string meant for humans to read, eg. using [`FormatOptions::pretty`].
+    /// 
+    /// This is the same as [`to_string_with`](IniDocument::to_string_with), but takes the options by value.
+    pub fn to_string_pretty(&self, opts: FormatOptions) -> String {
+        self.to_string_with(&opts)
+    }
+    /// Turn a document back into a string with sections and keys in the order given by two comparators,
+    /// as set by [`FormatOptions::section_order`] and [`FormatOptions::key_order`].
+    /// 
+    /// The unnamed section is ordered along with the others, with the empty string as its name.
+    /// 
+    /// ```
+    /// # use innit::*;
+    /// let document = IniDocument::from_string("[b]\nfoo = bar\n[general]\nfoo = baz\n[a]\nfoo = bop").unwrap();
+    /// // put `[general]` first, then the rest in alphabetical order
+    /// let written = document.to_string_ordered_by(|a, b| (a != "general").cmp(&(b != "general")).then(a.cmp(b)), str::cmp);
+    /// let headers: Vec<&str> = written.lines().filter(|l| l.starts_with('[')).collect();
+    /// assert_eq!(headers, ["[general]", "[a]", "[b]"]);
+    /// ```
+    pub fn to_string_ordered_by<F, G>(&self, section_cmp: F, key_cmp: G) -> String
+    where F: Fn(&str, &str) -> Ordering, G: Fn(&str, &str) -> Ordering {
+        self.write_ordered(&FormatOptions::default(), Some(&section_cmp), Some(&key_cmp))
+    }
+}
+
+/// Turn a document back into its string representation. Ordering of sections, keys and values is not preserved, due to limitations of Rust's hashmap struct,
+/// unless the `sorted` feature is enabled.
+/// 
+/// Documents parsed with [`IniParser::preserve_formatting`] are written with every line that hasn't changed kept exactly as it was.
+/// Keys and values are quoted the same way the document was parsed, and values with newlines in them are always quoted,
+/// so they can be parsed back with [`IniParser::quoted_values`] instead of splitting the value over several lines.
+impl fmt::Display for IniDocument {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.layout.is_kept() {
+            f.write_str(&self.write_with_layout(&self.layout))
+        }
+        else {
+            f.write_str(&self.to_string_with(&self.display_options()))
+        }
+    }
+}
+
+/// Options for turning an [`IniDocument`] back into a string.
+#[derive(Default)]
+pub struct FormatOptions {
+    /// Where the keys of the unnamed section go. Defaults to [`GlobalPosition::First`].
+    pub global_position: GlobalPosition,
+    /// A function applied to every value as it's written, without changing the document itself.
+    /// It's called with the section, key and value, in that order, and returns the value to write.
+    /// 
+    /// This is useful for redacting secrets when logging a document. Defaults to `None`, which writes values as they are.
+    pub value_transform: Option<Box<ValueTransform>>,
+    /// Write keys with empty values on their own, without an equals sign, to go with [`IniParser::bare_keys`].
+    /// Defaults to `false`, which writes them as `key = `.
+    pub bare_keys: bool,
+    /// Pad keys so that the equals signs in each section line up, based on the longest key in the section. Defaults to `false`.
+    pub align_equals: bool,
+    /// Put a blank line before each section header, apart from the first thing in the document. Defaults to `false`.
+    pub blank_line_between_sections: bool,
+    /// How to space out the equals sign between keys and values. Defaults to [`Spacing::Both`].
+    pub spacing: Spacing,
+    /// Wrap values in double quotes if they wouldn't be parsed back the same otherwise, to go with [`IniParser::quoted_values`].
+    /// This is the only way to write values with line breaks in them, which would otherwise split the value over several lines.
+    /// Defaults to `false`, which writes every value as it is. See [`try_to_string_with`](IniDocument::try_to_string_with).
+    pub quoted_values: bool,
+    /// Wrap keys in double quotes if they wouldn't be parsed back the same otherwise, like keys with spaces or equals signs in them,
+    /// to go with [`IniParser::quoted_keys`]. Defaults to `false`, which writes every key as it is.
+    pub quoted_keys: bool,
+    /// Write sections and keys in sorted order, instead of whatever order they're stored in. Defaults to `false`.
+    pub sorted: bool,
+    /// A comparator for the order sections are written in, including the unnamed section as the empty string.
+    /// When set, this overrides [`sorted`](FormatOptions::sorted) and [`global_position`](FormatOptions::global_position) for sections.
+    /// Defaults to `None`.
+    /// 
+    /// Note that a document with the unnamed section anywhere but first will not parse back the same.
+    pub section_order: Option<Box<Comparator>>,
+    /// A comparator for the order keys are written in within each section. When set, this overrides [`sorted`](FormatOptions::sorted) for keys.
+    /// Defaults to `None`.
+    pub key_order: Option<Box<Comparator>>,
+    /// The line ending to use. Defaults to [`LineEnding::CrLf`] when the `crlf` feature is enabled, and [`LineEnding::Lf`] otherwise.
+    pub line_ending: LineEnding,
+}
+impl FormatOptions {
+    /// Options for output meant for humans to read, with equals signs lined up and blank lines between sections.
+    pub fn pretty() -> FormatOptions {
+        FormatOptions {
+            align_equals: true,
+            blank_line_between_sections: true,
+            ..Default::default()
+        }
+    }
+}
+impl fmt::Debug for FormatOptions {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("FormatOptions")
+            .field("global_position", &self.global_position)
+            .field("value_transform", &self.value_transform.as_ref().map(|_| ".."))
+            .field("bare_keys", &self.bare_keys)
+            .field("align_equals", &self.align_equals)
+            .field("blank_line_between_sections", &self.blank_line_between_sections)
+            .field("spacing", &self.spacing)
+            .field("quoted_values", &self.quoted_values)
+            .field("quoted_keys", &self.quoted_keys)
+            .field("sorted", &self.sorted)
+            .field("section_order", &self.section_order.as_ref().map(|_| ".."))
+            .field("key_order", &self.key_order.as_ref().map(|_| ".."))
+            .field("line_ending", &self.line_ending)
+            .finish()
+    }
+}
+
+/// The old name for [`FormatOptions`], from before they were renamed.
+#[deprecated(note = "renamed to `FormatOptions`")]
+pub type WriteOptions = FormatOptions;
+
+/// The type of [`FormatOptions::value_transform`], taking a section, key and value.
+pub type ValueTransform = dyn Fn(&str, &str, &str) -> String;
+/// The type of [`FormatOptions::section_order`] and [`FormatOptions::key_order`], comparing two names.
+pub type Comparator = dyn Fn(&str, &str) -> Ordering;
+/// a comparator that's only needed for one call, so it doesn't have to be `'static`
+type BorrowedComparator<'a> = dyn Fn(&str, &str) -> Ordering + 'a;
+
+/// Where the keys of the unnamed section are written relative to the named sections.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum GlobalPosition {
+    /// Before the first section header, as INI expects.
+    #[default]
+    First,
+    /// After the last section.
+    /// 
+    /// Note that a document written this way will not parse back the same, as the keys will end up in the last section.
+    Last,
+}
+
+/// How the equals sign between keys and values is spaced out when writing a document.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum Spacing {
+    /// `key = value`
+    #[default]
+    Both,
+    /// `key=value`
+    None,
+    /// `key =value`
+    Before,
+}
+impl Spacing {
+    pub(crate) fn equals(&self) -> &'static str {
+        match self {
+            Spacing::Both => " = ",
+            Spacing::None => "=",
+            Spacing::Before => " =",
+        }
+    }
+    /// the same spacing around a delimiter other than `=`
+    pub(crate) fn around(&self, delimiter: char) -> String {
+        self.equals().replace('=', delimiter.encode_utf8(&mut [0; 4]))
+    }
+}
+
+/// The line ending used when writing a document.
+/// 
+/// The default is [`LineEnding::CrLf`] when the `crlf` feature is enabled, and [`LineEnding::Lf`] otherwise.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LineEnding {
+    /// `\n`, as used on Unix-likes.
+    Lf,
+    /// `\r\n`, as used on Windows.
+    CrLf,
+}
+impl LineEnding {
+    #[cfg(not(feature = "crlf"))]
+    pub(crate) const DEFAULT: LineEnding = LineEnding::Lf;
+    #[cfg(feature = "crlf")]
+    pub(crate) const DEFAULT: LineEnding = LineEnding::CrLf;
+
+    /// Get the line ending as a string.
+    pub const fn as_str(&self) -> &'static str {
+        match self {
+            LineEnding::Lf => "\n",
+            LineEnding::CrLf => "\r\n",
+        }
+    }
+}
+impl Default for LineEnding {
+    fn default() -> LineEnding {
+        LineEnding::DEFAULT
+    }
+}
+
+/// Escape a section name so it can be written between square brackets and parsed back the same.
+/// 
+/// Backslashes and closing brackets are escaped with a backslash, and so is whitespace at either end,
+/// so it isn't trimmed when the name is read back. Names with line breaks in them can't be written,
+/// see [`IniDocument::try_insert_validated`].
+pub(crate) fn escape_section_name(name: &str) -> Cow<'_, str> {
+    let inner = name.trim();
+    if !name.contains(['\\', ']']) && inner.len() == name.len() {
+        return Cow::Borrowed(name)
+    }
+    let start = name.len() - name.trim_start().len();
+    let mut ret = String::with_capacity(name.len() + 2);
+    for (i, c) in name.char_indices() {
+        if matches!(c, '\\' | ']') || c.is_whitespace() && (i < start || i >= start + inner.len()) {
+            ret.push('\\');
+        }
+        ret.push(c)
+    }
+    Cow::Owned(ret)
+}
+
+/// format a section's keys and values
+pub(crate) fn fmt_section(name: &str, h: &Section, meta: Option<&Map<String, KeyMeta>>, arrays: Option<&Map<String, Vec<String>>>, options: &FormatOptions) -> String {
+    let mut ret = String::new();
+    // writing to a string can't fail
+    let _ = write_section(&mut ret, name, h, meta, arrays, options, options.key_order.as_deref());
+    ret
+}
+/// write a section's keys and values, one line at a time
+fn write_section<W: fmt::Write>(ret: &mut W, name: &str, h: &Section, meta: Option<&Map<String, KeyMeta>>, arrays: Option<&Map<String, Vec<String>>>, options: &FormatOptions, key_order: Option<&BorrowedComparator<'_>>) -> fmt::Result {
+    let width = if options.align_equals {
+        let array_keys = arrays.into_iter().flat_map(|a| a.keys()).map(|k| written_key(k, options).chars().count() + 2);
+        h.keys().map(|k| written_key(k, options).chars().count()).chain(array_keys).max().unwrap_or(0)
+    }
+    else {
+        0
+    };
+    let equals = options.spacing.equals();
+    let nl = options.line_ending.as_str();
+
+    let mut keys: Vec<(&String, &String)> = h.0.iter().collect();
+    if let Some(cmp) = key_order {
+        keys.sort_by(|(a, _), (b, _)| cmp(a, b))
+    }
+    else if options.sorted {
+        keys.sort_by_key(|(k, _)| *k)
+    }
+
+    for (k, v) in keys {
+        if let Some(comment) = meta.and_then(|m| m.get(k)).and_then(|m| m.comment.as_ref()) {
+            for line in comment.split('\n') {
+                write!(ret, "# {}{}", line, nl)?
+            }
+        }
+        let v = written_value(name, k, v, options);
+        let key = written_key(k, options);
+        if options.bare_keys && v == "" {
+            write!(ret, "{}{}", key, nl)?
+        }
+        else {
+            write!(ret, "{:width$}{}{}{}", key, equals, v, nl)?
+        }
+    }
+
+    // array keys go after the ordinary ones, with each value on its own line
+    let mut arrays: Vec<(&String, &Vec<String>)> = arrays.into_iter().flatten().collect();
+    if let Some(cmp) = key_order {
+        arrays.sort_by(|(a, _), (b, _)| cmp(a, b))
+    }
+    else if options.sorted {
+        arrays.sort_by_key(|(k, _)| *k)
+    }
+    for (k, values) in arrays {
+        let key = format!("{}[]", written_key(k, options));
+        for v in values {
+            let v = written_value(name, k, v, options);
+            write!(ret, "{:width$}{}{}{}", key, equals, v, nl)?
+        }
+    }
+
+    Ok(())
+}
+/// lets sections be written straight to an [`io::Write`], keeping the error that stopped it
+struct IoAdapter<W> {
+    inner: W,
+    error: Option<io::Error>,
+}
+impl<W: io::Write> fmt::Write for IoAdapter<W> {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        self.inner.write_all(s.as_bytes()).map_err(|e| {
+            self.error = Some(e);
+            fmt::Error
+        })
+    }
+}
+/// a value as it should be written, after any transform and quoting
+pub(crate) fn written_value<'a>(section: &str, key: &str, value: &'a str, options: &FormatOptions) -> Cow<'a, str> {
+    let value = match &options.value_transform {
+        Some(f) => f(section, key, value).into(),
+        None => Cow::Borrowed(value)
+    };
+    if options.quoted_values {
+        quote(value)
+    }
+    else {
+        value
+    }
+}
+/// why a key can't be written with these options, if it can't
+pub(crate) fn unwritable_key(key: &str, options: &FormatOptions) -> Option<&'static str> {
+    if options.quoted_keys {
+        return None
+    }
+    if key.contains('=') {
+        Some("keys can't contain an equals sign")
+    }
+    else if key.contains(['\n', '\r']) {
+        Some("keys can't contain line breaks")
+    }
+    else if key.trim() != key {
+        Some("keys can't start or end with whitespace")
+    }
+    else if key.starts_with(['#', ';', '[']) {
+        Some("keys can't start with a comment prefix or an opening bracket")
+    }
+    else if key.ends_with("[]") {
+        Some("keys can't end with `[]`, since they'd be read as array keys")
+    }
+    else if options.quoted_values && key.contains('"') {
+        // the parser looks for quotes before the delimiter when values are quoted
+        Some("keys can't contain double quotes when values are quoted")
+    }
+    else {
+        None
+    }
+}
+/// why a value can't be written with these options, if it can't
+pub(crate) fn unwritable_value(value: &str, options: &FormatOptions) -> Option<&'static str> {
+    if options.quoted_values {
+        None
+    }
+    else if value.contains(['\n', '\r']) {
+        Some("values can't contain line breaks without quoting")
+    }
+    else if value.trim() != value {
+        Some("values can't start or end with whitespace without quoting")
+    }
+    else {
+        None
+    }
+}
+/// a key as it should be written, quoted if that's on and it needs to be
+pub(crate) fn written_key<'a>(key: &'a str, options: &FormatOptions) -> Cow<'a, str> {
+    // anything that would end the key early, be taken for another kind of line, or be trimmed off
+    let plain = !key.is_empty() && !key.contains(|c: char| c.is_whitespace() || c == '=' || c == '"')
+        && !key.starts_with(['[', '#', ';']) && !key.ends_with("[]");
+    if !options.quoted_keys || plain {
+        Cow::Borrowed(key)
+    }
+    else {
+        Cow::Owned(escape_quoted(key))
+    }
+}
+/// wrap a value in quotes if it wouldn't be parsed back the same without them, escaping anything that needs it
+fn quote(value: Cow<'_, str>) -> Cow<'_, str> {
+    let quoted = value.len() >= 2 && value.starts_with('"') && value.ends_with('"');
+    if !quoted && !value.contains(['\n', '\r']) && value.trim() == value {
+        return value
+    }
+    Cow::Owned(escape_quoted(&value))
+}
+/// wrap a string in quotes, escaping anything that needs it
+fn escape_quoted(s: &str) -> String {
+    let mut ret = String::with_capacity(s.len() + 2);
+    ret.push('"');
+    for c in s.chars() {
+        match c {
+            '"' | '\\' => {
+                ret.push('\\');
+                ret.push(c)
+            }
+            '\n' => ret.push_str("\\n"),
+            '\r' => ret.push_str("\\r"),
+            '\t' => ret.push_str("\\t"),
+            _ => ret.push(c)
+        }
+    }
+    ret.push('"');
+    ret
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn global_first() {
+        let ini = r"foo = bar
+[section1]
+foo = baz";
+        let document = IniDocument::from_string(ini).unwrap();
+        let options = FormatOptions { global_position: GlobalPosition::First, ..Default::default() };
+        assert_eq!(document.to_string_with(&options), format!("foo = bar{0}[section1]{0}foo = baz{0}", LINE_DELIM));
+        assert_eq!(document.to_string(), document.to_string_with(&options));
+    }
+
+    #[test]
+    #[allow(deprecated)]
+    fn write_options_alias() {
+        let options = WriteOptions { global_position: GlobalPosition::Last, value_transform: Some(Box::new(|_, _, v| v.to_uppercase())), ..Default::default() };
+        let document = IniDocument::from_string("foo = bar\n[section1]\nfoo = baz").unwrap();
+        assert_eq!(document.to_string_with(&options), format!("[section1]{0}foo = BAZ{0}foo = BAR{0}", LINE_DELIM));
+    }
+
+    #[test]
+    fn global_last() {
+        let ini = r"foo = bar
+[section1]
+foo = baz";
+        let document = IniDocument::from_string(ini).unwrap();
+        let options = FormatOptions { global_position: GlobalPosition::Last, ..Default::default() };
+        assert_eq!(document.to_string_with(&options), format!("[section1]{0}foo = baz{0}foo = bar{0}", LINE_DELIM));
+    }
+
+    #[test]
+    fn value_transform() {
+        let ini = r"user = me
+password = hunter2
+[db]
+password = letmein";
+        let document = IniDocument::from_string(ini).unwrap();
+        let options = FormatOptions {
+            value_transform: Some(Box::new(|_, k, v| if k == "password" { "****".into() } else { v.into() })),
+            ..Default::default()
+        };
+        let redacted = IniDocument::from_string(document.to_string_with(&options)).unwrap();
+        assert_eq!(redacted.get("user", ""), Some("me"));
+        assert_eq!(redacted.get("password", ""), Some("****"));
+        assert_eq!(redacted.get("password", "db"), Some("****"));
+
+        // the document itself is untouched
+        assert_eq!(document.get("password", ""), Some("hunter2"));
+    }
+
+    #[test]
+    fn pretty() {
+        let ini = r"a = 1
+long_key = 2
+[section1]
+foo = bar
+[section2]
+bazbop = qux";
+        let document = IniDocument::from_string(ini).unwrap();
+        let pretty = document.to_string_pretty(FormatOptions::pretty());
+        let lines: Vec<&str> = pretty.split(LINE_DELIM).collect();
+        assert!(lines.contains(&"a        = 1"));
+        assert!(lines.contains(&"long_key = 2"));
+        assert!(lines.contains(&"foo = bar"));
+        // blank lines before both headers, plus the nothing after the last line ending
+        assert_eq!(lines.iter().filter(|l| l.is_empty()).count(), 3);
+        assert!(pretty.contains(&format!("{0}{0}[section1]", LINE_DELIM)));
+        assert!(pretty.contains(&format!("{0}{0}[section2]", LINE_DELIM)));
+
+        let tight = FormatOptions { spacing: Spacing::None, ..FormatOptions::pretty() };
+        let tight = document.to_string_pretty(tight);
+        assert!(tight.split(LINE_DELIM).any(|l| l == "a       =1"));
+
+        assert_eq!(IniDocument::from_string(pretty).unwrap(), document);
+    }
+
+    #[test]
+    fn blank_line_between_sections() {
+        let document = IniDocument::from_string("a = 1\n[section1]\nfoo = bar\n[section2]\nbaz = bop").unwrap();
+        let options = FormatOptions { blank_line_between_sections: true, sorted: true, ..Default::default() };
+        let expected = "a = 1\n\n[section1]\nfoo = bar\n\n[section2]\nbaz = bop\n";
+        assert_eq!(document.to_string_with(&options), expected.replace('\n', LINE_DELIM));
+
+        let mut named = document.clone();
+        named.remove_section("");
+        let expected = "[section1]\nfoo = bar\n\n[section2]\nbaz = bop\n";
+        assert_eq!(named.to_string_with(&options), expected.replace('\n', LINE_DELIM));
+        assert!(!document.to_string().contains(&LINE_DELIM.repeat(2)));
+    }
+
+    #[test]
+    fn spacing() {
+        let document = IniDocument::from_string("[section1]\nfoo = bar").unwrap();
+        let expected = [
+            (Spacing::Both, "[section1]\nfoo = bar\n"),
+            (Spacing::None, "[section1]\nfoo=bar\n"),
+            (Spacing::Before, "[section1]\nfoo =bar\n"),
+        ];
+        for (spacing, bytes) in expected {
+            let options = FormatOptions { spacing, ..Default::default() };
+            assert_eq!(document.to_string_with(&options), bytes.replace('\n', LINE_DELIM));
+        }
+        assert_eq!(document.to_string(), document.to_string_with(&FormatOptions::default()));
+    }
+
+    #[test]
+    fn line_breaks_in_values() {
+        let mut document = IniDocument::empty();
+        document.insert("multi", "one\ntwo\r\nthree", "section1");
+        document.insert("padded", "  spaces  ", "section1");
+        document.insert("quoted", "\"already\" \\ quoted\"", "section1");
+        document.insert("plain", "nothing to see", "section1");
+        document.push("items", "a\nb", "section1");
+
+        let options = FormatOptions { quoted_values: true, sorted: true, line_ending: LineEnding::Lf, ..Default::default() };
+        let written = document.try_to_string_with(&options).unwrap();
+        assert!(written.contains("plain = nothing to see\n"));
+        assert!(written.contains("multi = \"one\\ntwo\\r\\nthree\"\n"));
+        let parsed = IniParser::new().quoted_values(true).array_keys(true).parse(&written).unwrap();
+        assert_eq!(parsed, document);
+
+        let error = InnitError::LineBreakInValue { key: "multi".into(), section: "section1".into() };
+        document.remove("items", "section1");
+        assert_eq!(document.try_to_string_with(&FormatOptions::default()), Err(error));
+        document.remove("multi", "section1");
+        assert_eq!(
+            document.try_to_string_with(&FormatOptions::default()),
+            Err(InnitError::LineBreakInValue { key: "items".into(), section: "section1".into() })
+        );
+        document.remove_all("items", "section1");
+        assert_eq!(document.try_to_string_with(&FormatOptions::default()), Ok(document.to_string()));
+
+        // plain to_string quotes the same way the document was parsed
+        let parser = IniParser::new().quoted_values(true);
+        let document = parser.parse("a = \"x\\ny\"\nb = \" padded \"").unwrap();
+        assert_eq!(document.get("a", ""), Some("x\ny"));
+        assert_eq!(document.to_string_with(&FormatOptions::default()).lines().count(), 3);
+        assert_eq!(document.to_string().lines().count(), 2);
+        assert_eq!(parser.parse(document.to_string()).unwrap(), document);
+
+        // and quotes values with line breaks even if it wasn't parsed with quotes, since they can't be written otherwise
+        let mut document = IniDocument::empty();
+        document.insert("multi", "one\ntwo", "");
+        assert_eq!(document.to_string(), format!("multi = \"one\\ntwo\"{}", LINE_DELIM));
+        assert_eq!(parser.parse(document.to_string()).unwrap(), document);
+        document.clear();
+        document.insert("plain", "\"as is\"", "");
+        assert_eq!(document.to_string(), format!("plain = \"as is\"{}", LINE_DELIM));
+    }
+
+    #[test]
+    fn write_section_to() {
+        let ini = "top = 1\n[section1]\n# a comment\nfoo = bar\nlonger = baz\n[section2]\nqux = quux";
+        let document = IniDocument::from_string(ini).unwrap();
+        let options = FormatOptions { align_equals: true, sorted: true, line_ending: LineEnding::Lf, ..Default::default() };
+        let mut out = Vec::new();
+        document.write_section_to_with("section1", &mut out, &options).unwrap();
+        assert_eq!(out, b"[section1]\n# a comment\nfoo    = bar\nlonger = baz\n");
+
+        // sections written one after another are the same as the whole document
+        let mut out = Vec::new();
+        for section in ["", "section1", "section2"] {
+            document.write_section_to_with(section, &mut out, &options).unwrap();
+        }
+        assert_eq!(String::from_utf8(out).unwrap(), document.to_string_with(&options));
+
+        let mut out = Vec::new();
+        document.write_section_to("section2", &mut out).unwrap();
+        assert_eq!(out, ["[section2]", "qux = quux", ""].join(LINE_DELIM).as_bytes());
+
+        let err = document.write_section_to("section3", Vec::new()).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::NotFound);
+        assert_eq!(err.to_string(), "section `section3` does not exist");
+        let err = document.write_section_to("section1", &mut [0u8; 4][..]).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::WriteZero);
+
+        // quoted the same way as to_string
+        let document = IniParser::new().quoted_values(true).parse("[section1]\nfoo = \" padded \"").unwrap();
+        let mut out = Vec::new();
+        document.write_section_to("section1", &mut out).unwrap();
+        assert_eq!(out, document.to_string().as_bytes());
+    }
+
+    #[test]
+    fn canonical() {
+        let mut a = IniDocument::empty();
+        a.insert("zeta", "1", "");
+        a.insert("alpha", "2", "");
+        a.insert("foo", "bar", "section2");
+        a.insert("b", "c", "section1");
+        a.insert("a", "d", "section1");
+        let mut b = IniDocument::empty();
+        b.insert("a", "d", "section1");
+        b.insert("foo", "bar", "section2");
+        b.insert("b", "c", "section1");
+        b.insert("alpha", "2", "");
+        b.insert("zeta", "1", "");
+
+        let canonical = "alpha = 2\nzeta = 1\n[section1]\na = d\nb = c\n[section2]\nfoo = bar\n";
+        assert_eq!(a.to_canonical_string(), canonical);
+        assert_eq!(b.to_canonical_string().as_bytes(), a.to_canonical_string().as_bytes());
+
+        let parsed = IniParser::new().preserve_formatting(true).parse("[section2]\r\nfoo=bar\r\n[section1]\r\nb =  c\r\na= d\r\n[section3]\r\nzeta=1\r\nalpha=2").unwrap();
+        assert_ne!(parsed.to_string(), canonical);
+        let mut parsed = parsed;
+        let moved = parsed.remove_section("section3").unwrap();
+        parsed.extend_section("", moved.iter());
+        assert_eq!(parsed.to_canonical_string(), canonical);
+        assert_eq!(IniDocument::empty().to_canonical_string(), "");
+    }
+
+    #[test]
+    fn sorted() {
+        let ini = r"zeta = 1
+alpha = 2
+[section2]
+b = 3
+a = 4
+[section1]
+d = 5
+c = 6";
+        let document = IniDocument::from_string(ini).unwrap();
+        let expected = "alpha = 2\nzeta = 1\n[section1]\nc = 6\nd = 5\n[section2]\na = 4\nb = 3\n";
+        assert_eq!(document.to_string_sorted(), expected.replace('\n', LINE_DELIM));
+    }
+
+    #[test]
+    fn ordered_by() {
+        let ini = r"zeta = 1
+alpha = 2
+[section2]
+b = 3
+a = 4
+c[] = 7
+d[] = 8
+[section1]
+d = 5
+c = 6";
+        let mut document = IniParser::new().array_keys(true).parse(ini).unwrap();
+        let reversed = |a: &str, b: &str| b.cmp(a);
+        let expected = "[section2]\nb = 3\na = 4\nd[] = 8\nc[] = 7\n[section1]\nd = 5\nc = 6\nzeta = 1\nalpha = 2\n";
+        assert_eq!(document.to_string_ordered_by(reversed, reversed), expected.replace('\n', LINE_DELIM));
+
+        document.remove_section("");
+        let expected = "[section1]\nd = 5\nc = 6\n[section2]\nb = 3\na = 4\nd[] = 8\nc[] = 7\n";
+        assert_eq!(document.to_string_ordered_by(str::cmp, reversed), expected.replace('\n', LINE_DELIM));
+
+        // the comparators can borrow from their surroundings
+        let first = String::from("section2");
+        let written = document.to_string_ordered_by(|a, b| (a != first).cmp(&(b != first)).then(a.cmp(b)), str::cmp);
+        assert!(written.starts_with("[section2]"));
+    }
+
+    #[test]
+    fn line_endings() {
+        let document = IniDocument::from_string("[section1]\nfoo = bar").unwrap();
+        let lf = FormatOptions { line_ending: LineEnding::Lf, ..Default::default() };
+        assert_eq!(document.to_string_with(&lf), "[section1]\nfoo = bar\n");
+        let crlf = FormatOptions { line_ending: LineEnding::CrLf, ..Default::default() };
+        assert_eq!(document.to_string_with(&crlf), "[section1]\r\nfoo = bar\r\n");
+    }
+
+    #[test]
+    #[cfg(feature = "sorted")]
+    fn sorted_feature() {
+        let ini = r"zeta = 1
+alpha = 2
+[section2]
+b = 3
+a = 4
+[section1]
+foo = bar";
+        let document = IniDocument::from_string(ini).unwrap();
+        let expected = ["alpha = 2", "zeta = 1", "[section1]", "foo = bar", "[section2]", "a = 4", "b = 3", ""].join(LINE_DELIM);
+        assert_eq!(document.to_string(), expected);
+        assert_eq!(document.to_string_sorted(), expected);
+        let keys: Vec<&str> = document.get_section("section2").unwrap().keys().collect();
+        assert_eq!(keys, ["a", "b"]);
+    }
+}